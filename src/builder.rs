@@ -0,0 +1,345 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use bit_ops::BitOps;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    emitter::{InstrType, Program},
+    parser::{
+        CONDITIONS, Imm, alu_opcode, check_mvi_imm, d1_dest, d1_source, emit_bus_mov, emit_clr_a,
+        ensure_alone, mvi_dest,
+    },
+    tokeniser::ScuDspToken,
+};
+
+type T = ScuDspToken;
+
+/// Returns the encoding of a condition code, e.g. [`ScuDspToken::Nz`]
+fn condition_bits(cond: &ScuDspToken) -> color_eyre::Result<u32> {
+    match CONDITIONS.iter().find(|(tok, _)| *tok == cond) {
+        Some((_, bits)) => Ok(*bits),
+        None => Err(eyre!("Error: {} is not a condition code", cond.as_ref())),
+    }
+}
+
+/// Builds bundles directly, for tools that generate DSP code without going through source text.
+/// Each bundle is started with [`Program::begin_bundle`], filled with the `add_*` methods and
+/// committed with [`Program::end_bundle`], which checks it the same way the assembler does.
+/// Operands are registers (or defines aliasing them) and plain numbers; labels and DMA aren't
+/// supported.
+impl Program {
+    /// Starts building a new bundle
+    pub fn begin_bundle(&mut self) -> color_eyre::Result<()> {
+        if self.is_emitting() {
+            return Err(eyre!(
+                "Error: A bundle is already being built; call end_bundle() first"
+            ));
+        }
+        self.begin();
+
+        Ok(())
+    }
+
+    fn ensure_building(&self) -> color_eyre::Result<()> {
+        if !self.is_emitting() {
+            return Err(eyre!(
+                "Error: No bundle is being built; call begin_bundle() first"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Substitutes the register a define stands for, if the token is one
+    fn resolve_operand(&self, tok: ScuDspToken) -> color_eyre::Result<ScuDspToken> {
+        match tok {
+            T::Ident(name) => self.resolve_token(name),
+            tok => Ok(tok),
+        }
+    }
+
+    /// Adds an ALU operation, e.g. [`ScuDspToken::Ad2`]
+    pub fn add_alu(&mut self, op: ScuDspToken) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        let Some(opcode) = alu_opcode(&op) else {
+            return Err(eyre!("Error: {} is not an ALU operation", op.as_ref()));
+        };
+        self.emit(opcode << 26);
+        self.register_emitted(InstrType::Alu);
+
+        Ok(())
+    }
+
+    /// Adds an X-bus MOV, i.e. `MOV src,X`, `MOV src,P` or `MOV MUL,P`
+    pub fn add_xbus_mov(&mut self, src: ScuDspToken, dest: ScuDspToken) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        if !matches!(dest, T::X | T::P) {
+            return Err(eyre!(
+                "Error: {} is not an X-bus destination",
+                dest.as_ref()
+            ));
+        }
+
+        emit_bus_mov(&src, &dest, self)
+    }
+
+    /// Adds a Y-bus MOV, i.e. `MOV src,Y` or `MOV ALU,A`
+    pub fn add_ybus_mov(&mut self, src: ScuDspToken, dest: ScuDspToken) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        if !matches!(dest, T::Y | T::A) {
+            return Err(eyre!("Error: {} is not a Y-bus destination", dest.as_ref()));
+        }
+
+        emit_bus_mov(&src, &dest, self)
+    }
+
+    /// Adds a D1-bus MOV from data RAM or the accumulator, e.g. `MOV M0,RX` or `MOV ALL,MC2`
+    pub fn add_d1_mov(&mut self, src: ScuDspToken, dest: ScuDspToken) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        let src = self.resolve_operand(src)?;
+        let dest = self.resolve_operand(dest)?;
+        let Some(src_bits) = d1_source(&src) else {
+            return Err(eyre!("Error: {} is not a D1-bus source", src.as_ref()));
+        };
+        let Some(dest_bits) = d1_dest(&dest) else {
+            return Err(eyre!(
+                "Error: {} is not a D1-bus destination",
+                dest.as_ref()
+            ));
+        };
+
+        if dest == T::Lop {
+            self.mark_lop_loaded();
+        }
+        // D1-bus control 11 selects a data RAM or ALU source, manual pp. 117
+        self.emit(0_u32.set_bit(13).set_bit(12) | (dest_bits << 8) | src_bits);
+        self.register_emitted(InstrType::D1Bus);
+
+        Ok(())
+    }
+
+    /// Adds `MVI value,dest`, or `MVI value,dest,cond` with a condition. MVI takes up the whole
+    /// word, so it must be the only instruction in its bundle.
+    pub fn add_mvi(
+        &mut self,
+        value: i32,
+        dest: ScuDspToken,
+        cond: Option<ScuDspToken>,
+    ) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        ensure_alone(&T::Mvi, self)?;
+        let dest = self.resolve_operand(dest)?;
+        let Some(dest_bits) = mvi_dest(&dest) else {
+            return Err(eyre!("Error: {} is not an MVI destination", dest.as_ref()));
+        };
+
+        let word = 0_u32.set_bit(31) | (dest_bits << 26);
+        let imm = Imm::Value(value as u32);
+        let word = match cond {
+            Some(cond) => {
+                // the condition eats into the immediate, leaving 19 bits (signed)
+                check_mvi_imm(&imm, 19, "MVI Imm, [d], cond")?;
+                word | (condition_bits(&cond)? << 19) | (value as u32 & 0x7_FFFF)
+            }
+            None => {
+                check_mvi_imm(&imm, 25, "MVI Imm, [d]")?;
+                word | (value as u32 & 0x1FF_FFFF)
+            }
+        };
+
+        if dest == T::Lop {
+            self.mark_lop_loaded();
+        }
+        self.emit(word);
+        self.register_emitted(InstrType::LoadImm);
+
+        Ok(())
+    }
+
+    /// Adds `JMP target`, or `JMP cond,target` with a condition. JMP takes up the whole word, so
+    /// it must be the only instruction in its bundle.
+    pub fn add_jmp(&mut self, target: u8, cond: Option<ScuDspToken>) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        ensure_alone(&T::Jmp, self)?;
+        let cond_bits = match cond {
+            Some(cond) => condition_bits(&cond)?,
+            None => 0,
+        };
+
+        self.emit(0_u32.set_bit(31).set_bit(30).set_bit(28) | (cond_bits << 19) | target as u32);
+        self.register_emitted(InstrType::FlowControl);
+
+        Ok(())
+    }
+
+    /// Adds a `CLR A`
+    pub fn add_clr_a(&mut self) -> color_eyre::Result<()> {
+        self.ensure_building()?;
+        emit_clr_a(self);
+
+        Ok(())
+    }
+
+    /// Validates and commits the bundle, returning its word. An illegal bundle is thrown away, so
+    /// a new one can be started straight after.
+    pub fn end_bundle(&mut self) -> color_eyre::Result<u32> {
+        self.ensure_building()?;
+        if self.current_bundle_instructions() == 0 {
+            self.discard();
+            return Err(eyre!("Error: Bundle does not contain any instructions"));
+        }
+
+        if let Err(error) = self.validate_bundle() {
+            self.discard();
+            return Err(error.into());
+        }
+        self.flush()?;
+
+        Ok(*self.words().last().unwrap_or(&0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emitter::Define, parser::assemble_bundle};
+
+    #[test]
+    fn test_bundle_builder() -> color_eyre::Result<()> {
+        let mut prog = Program::default();
+
+        prog.begin_bundle()?;
+        prog.add_alu(T::Ad2)?;
+        prog.add_xbus_mov(T::Mul, T::P)?;
+        prog.add_xbus_mov(T::Mc1, T::X)?;
+        prog.add_ybus_mov(T::M2, T::Y)?;
+        prog.add_clr_a()?;
+        let word = prog.end_bundle()?;
+        assert_eq!(
+            word,
+            assemble_bundle("AD2  MOV MUL,P  MOV MC1,X  MOV M2,Y  CLR A")?
+        );
+
+        prog.begin_bundle()?;
+        prog.add_ybus_mov(T::Alu, T::A)?;
+        prog.end_bundle()?;
+        assert_eq!(prog.words(), &[word, assemble_bundle("MOV ALU,A")?]);
+        assert_eq!(prog.pc(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_builder_misuse() -> color_eyre::Result<()> {
+        let mut prog = Program::default();
+
+        // adding outside a bundle, or starting one twice
+        assert!(prog.add_alu(T::And).is_err());
+        prog.begin_bundle()?;
+        assert!(prog.begin_bundle().is_err());
+
+        // wrong kinds of operand
+        assert!(prog.add_alu(T::M0).is_err());
+        assert!(prog.add_xbus_mov(T::M0, T::Y).is_err());
+        assert!(prog.add_ybus_mov(T::Mul, T::Y).is_err());
+
+        // two ALU ops is rejected, and the bundle thrown away
+        prog.add_alu(T::And)?;
+        prog.add_alu(T::Or)?;
+        assert!(prog.end_bundle().is_err());
+        assert!(prog.words().is_empty());
+
+        prog.begin_bundle()?;
+        assert!(prog.end_bundle().is_err());
+        prog.begin_bundle()?;
+        prog.add_alu(T::Nop)?;
+        assert_eq!(prog.end_bundle()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_builder_rejected_mov() -> color_eyre::Result<()> {
+        // a rejected MOV leaves nothing behind, so the destination can still be written
+        let mut prog = Program::default();
+        prog.begin_bundle()?;
+        assert!(prog.add_xbus_mov(T::Nz, T::X).is_err());
+        assert!(prog.add_ybus_mov(T::Rx, T::Y).is_err());
+        prog.add_xbus_mov(T::M0, T::X)?;
+        prog.add_ybus_mov(T::M1, T::Y)?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("MOV M0,X  MOV M1,Y")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_builder_alias() -> color_eyre::Result<()> {
+        let mut prog = Program::default();
+        prog.add_define("RAM".into(), Define::Token(T::M1))?;
+        prog.add_define("PRODUCT".into(), Define::Token(T::Mul))?;
+        prog.add_define("COUNT".into(), Define::Value(3))?;
+
+        prog.begin_bundle()?;
+        prog.add_xbus_mov(T::Ident("RAM".into()), T::X)?;
+        prog.add_xbus_mov(T::Ident("PRODUCT".into()), T::P)?;
+        assert!(prog.add_ybus_mov(T::Ident("COUNT".into()), T::Y).is_err());
+        assert!(prog.add_ybus_mov(T::Ident("NOPE".into()), T::Y).is_err());
+        assert_eq!(prog.end_bundle()?, assemble_bundle("MOV M1,X  MOV MUL,P")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_builder_whole_word() -> color_eyre::Result<()> {
+        let mut prog = Program::default();
+        prog.add_define("COUNTER".into(), Define::Token(T::Lop))?;
+
+        prog.begin_bundle()?;
+        prog.add_d1_mov(T::All, T::Mc2)?;
+        prog.add_alu(T::Sub)?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("SUB  MOV ALL,MC2")?);
+
+        prog.begin_bundle()?;
+        prog.add_mvi(-2, T::Mc1, None)?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("MVI #-2,MC1")?);
+        prog.begin_bundle()?;
+        prog.add_mvi(7, T::Rx, Some(T::Z))?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("MVI #7,RX,Z")?);
+        prog.begin_bundle()?;
+        prog.add_mvi(3, T::Ident("COUNTER".into()), None)?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("MVI #3,LOP")?);
+
+        prog.begin_bundle()?;
+        prog.add_jmp(0x10, None)?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("JMP $10")?);
+        prog.begin_bundle()?;
+        prog.add_jmp(0x10, Some(T::Nz))?;
+        assert_eq!(prog.end_bundle()?, assemble_bundle("JMP NZ,$10")?);
+
+        // whole-word instructions can't share a bundle, whichever order they're added in
+        prog.begin_bundle()?;
+        prog.add_clr_a()?;
+        assert!(prog.add_jmp(0x10, None).is_err());
+        assert!(prog.add_mvi(1, T::Mc0, None).is_err());
+        prog.end_bundle()?;
+        prog.begin_bundle()?;
+        prog.add_mvi(1, T::Mc0, None)?;
+        prog.add_clr_a()?;
+        assert!(prog.end_bundle().is_err());
+
+        // out of range, or the wrong kind of operand
+        prog.begin_bundle()?;
+        assert!(prog.add_mvi(1 << 18, T::Mc0, Some(T::Z)).is_err());
+        assert!(prog.add_mvi(1, T::M0, None).is_err());
+        assert!(prog.add_jmp(0, Some(T::Mc0)).is_err());
+        assert!(prog.add_d1_mov(T::Mul, T::Rx).is_err());
+        assert!(prog.add_d1_mov(T::M0, T::X).is_err());
+
+        Ok(())
+    }
+}
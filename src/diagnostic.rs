@@ -0,0 +1,276 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::ops::Range;
+
+use crate::{emitter::Program, parser::document_recovering, tokeniser::lex};
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found in the source, for editors and other tools to display. Lines and columns start
+/// at 1, and the span covers the offending token if there is one, or otherwise the offending line
+/// from its first non-blank character.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: u32,
+    pub column: u32,
+    pub end_column: u32,
+    pub message: String,
+    pub severity: Severity,
+
+    /// Short, stable category for tools to filter on, e.g. `syntax` or `illegal-bundle`
+    pub code: &'static str,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic spanning the given columns (starting at 0) of the given line (also
+    /// starting at 0) of the source, or the whole line if there are no columns
+    fn new(
+        source: &str,
+        line: u32,
+        columns: Option<Range<u32>>,
+        message: String,
+        severity: Severity,
+    ) -> Self {
+        let text = source.lines().nth(line as usize).unwrap_or("").trim_end();
+        // columns count characters, not bytes, so non-ASCII text doesn't shift the span
+        let width = text.chars().count();
+        let indent = width - text.trim_start().chars().count();
+
+        let columns = columns.unwrap_or(indent as u32..width as u32);
+        Self {
+            line: line + 1,
+            column: columns.start + 1,
+            end_column: columns.end + 1,
+            code: code(&message, severity),
+            message,
+            severity,
+        }
+    }
+
+    /// Creates an error that isn't about any line in particular, such as a failed check on the
+    /// assembled program. It's placed at the start of the file.
+    pub fn error(message: String) -> Self {
+        Self {
+            line: 1,
+            column: 1,
+            end_column: 1,
+            code: code(&message, Severity::Error),
+            message,
+            severity: Severity::Error,
+        }
+    }
+}
+
+/// Categorises a diagnostic. Errors all start with a prefix saying what sort they are, so
+/// categorise on that.
+fn code(message: &str, severity: Severity) -> &'static str {
+    match severity {
+        Severity::Warning => "warning",
+        Severity::Error if message.starts_with("Syntax error") => "syntax",
+        Severity::Error if message.starts_with("Illegal program") => "illegal-bundle",
+        Severity::Error => "error",
+    }
+}
+
+/// Quotes and escapes a string for JSON
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats diagnostics for `file` as a JSON array, one object per line
+pub fn diagnostics_json(file: &str, diagnostics: &[Diagnostic]) -> String {
+    let objects: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            format!(
+                "  {{\"file\": {}, \"line\": {}, \"column\": {}, \"severity\": \"{}\", \
+                \"message\": {}, \"code\": \"{}\"}}",
+                json_string(file),
+                diagnostic.line,
+                diagnostic.column,
+                diagnostic.severity.as_str(),
+                json_string(&diagnostic.message),
+                diagnostic.code
+            )
+        })
+        .collect();
+
+    if objects.is_empty() {
+        "[]\n".into()
+    } else {
+        format!("[\n{}\n]\n", objects.join(",\n"))
+    }
+}
+
+/// Assembles the source without producing any output, returning every error and warning found
+/// along the way, ordered by line. Errors don't stop assembly, so a single call reports as much as
+/// possible.
+pub fn diagnose(source: &str, relaxed: bool) -> Vec<Diagnostic> {
+    diagnose_with(source, Program::default(), relaxed)
+}
+
+/// Like [`diagnose`], but assembles into a program that has already been configured (e.g. with
+/// [`Program::set_require_org`])
+pub fn diagnose_with(source: &str, prog: Program, relaxed: bool) -> Vec<Diagnostic> {
+    diagnose_program(source, prog, relaxed).1
+}
+
+/// Like [`diagnose_with`], but also returns the program, which is fully assembled if none of the
+/// diagnostics are errors
+pub fn diagnose_program(
+    source: &str,
+    mut prog: Program,
+    relaxed: bool,
+) -> (Program, Vec<Diagnostic>) {
+    let mut tokens = lex(source);
+    let errors = document_recovering(&mut tokens, &mut prog, relaxed);
+
+    let mut diagnostics: Vec<Diagnostic> = errors
+        .into_iter()
+        .map(|error| {
+            Diagnostic::new(
+                source,
+                error.line,
+                error.column,
+                error.error.to_string(),
+                Severity::Error,
+            )
+        })
+        .chain(prog.warnings().iter().map(|warning| {
+            Diagnostic::new(
+                source,
+                warning.line,
+                None,
+                warning.message.clone(),
+                Severity::Warning,
+            )
+        }))
+        .collect();
+    // stable, so errors stay ahead of warnings on the same line
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+    (prog, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnose() {
+        let diagnostics = diagnose("CLR A\n  AD2 OR\nLPS\n", false);
+
+        assert_eq!(diagnostics.len(), 2);
+
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            (
+                diagnostics[0].line,
+                diagnostics[0].column,
+                diagnostics[0].end_column
+            ),
+            (2, 3, 9)
+        );
+        assert!(diagnostics[0].message.contains("more than one ALU"));
+
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert_eq!(diagnostics[1].line, 3);
+        assert!(diagnostics[1].message.contains("loop counter"));
+    }
+
+    #[test]
+    fn test_diagnose_token_column() {
+        // an error about a single token spans just that token
+        let diagnostics = diagnose("CLR A\nMOV FOO, X\n", false);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (
+                diagnostics[0].line,
+                diagnostics[0].column,
+                diagnostics[0].end_column
+            ),
+            (2, 5, 8)
+        );
+        assert!(diagnostics[0].message.contains("FOO"));
+    }
+
+    #[test]
+    fn test_diagnose_non_ascii() {
+        let diagnostics = diagnose("CLR A ; grüße\n  LPS ; für\n", false);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (
+                diagnostics[0].line,
+                diagnostics[0].column,
+                diagnostics[0].end_column
+            ),
+            (2, 3, 12)
+        );
+    }
+
+    #[test]
+    fn test_diagnose_recovers() {
+        // each bad line is reported, and the good ones in between still assemble
+        let diagnostics = diagnose("MOV ???\nCLR A\nJMP nowhere\nAD2 OR\n", false);
+        let lines: Vec<u32> = diagnostics.iter().map(|d| d.line).collect();
+
+        assert_eq!(lines, [1, 3, 4]);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.severity == Severity::Error)
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_json() {
+        let diagnostics = diagnose("CLR A\nMOV , X\n", false);
+
+        assert_eq!(
+            diagnostics_json("test.asm", &diagnostics),
+            "[\n  {\"file\": \"test.asm\", \"line\": 2, \"column\": 1, \"severity\": \"error\", \
+             \"message\": \"Syntax error: Expected a source operand, got ','\", \"code\": \"syntax\"}\n]\n"
+        );
+
+        // errors about the program as a whole go at the start of the file
+        let diagnostics = [Diagnostic::error("Error: Checksum mismatch".into())];
+        assert_eq!(
+            diagnostics_json("test.asm", &diagnostics),
+            "[\n  {\"file\": \"test.asm\", \"line\": 1, \"column\": 1, \"severity\": \"error\", \
+             \"message\": \"Error: Checksum mismatch\", \"code\": \"error\"}\n]\n"
+        );
+    }
+}
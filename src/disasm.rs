@@ -0,0 +1,693 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Decodes instruction words back into mnemonics. This is the inverse of the bit twiddling in the
+// parser, so see there (and the SCU manual) for where the encodings come from.
+
+use std::collections::HashMap;
+
+use bit_ops::BitOps;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    emitter::{InstrType, Program, WordSource, cycle_cost},
+    output::Columns,
+    parser::CONDITIONS,
+};
+
+/// ALU operations, indexed by bits 29-26
+const ALU_OPS: [Option<&str>; 16] = [
+    Some("NOP"),
+    Some("AND"),
+    Some("OR"),
+    Some("XOR"),
+    Some("ADD"),
+    Some("SUB"),
+    Some("AD2"),
+    None,
+    Some("SR"),
+    Some("RR"),
+    Some("SL"),
+    Some("RL"),
+    None,
+    None,
+    None,
+    Some("RL8"),
+];
+
+/// X-bus/Y-bus/DMA count sources, indexed by a 3-bit field
+const BUS_SOURCES: [&str; 8] = ["M0", "M1", "M2", "M3", "MC0", "MC1", "MC2", "MC3"];
+
+/// D1-bus sources, indexed by bits 3-0
+const D1_SOURCES: [Option<&str>; 16] = [
+    Some("M0"),
+    Some("M1"),
+    Some("M2"),
+    Some("M3"),
+    Some("MC0"),
+    Some("MC1"),
+    Some("MC2"),
+    Some("MC3"),
+    None,
+    Some("ALL"),
+    Some("ALH"),
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+/// D1-bus destinations, indexed by bits 11-8
+const D1_DESTS: [Option<&str>; 16] = [
+    Some("MC0"),
+    Some("MC1"),
+    Some("MC2"),
+    Some("MC3"),
+    Some("RX"),
+    Some("PL"),
+    Some("RA0"),
+    Some("WA0"),
+    None,
+    None,
+    Some("LOP"),
+    Some("TOP"),
+    Some("CT0"),
+    Some("CT1"),
+    Some("CT2"),
+    Some("CT3"),
+];
+
+/// MVI destinations, indexed by bits 29-26
+const MVI_DESTS: [Option<&str>; 16] = [
+    Some("MC0"),
+    Some("MC1"),
+    Some("MC2"),
+    Some("MC3"),
+    Some("RX"),
+    Some("PL"),
+    Some("RA0"),
+    Some("WA0"),
+    None,
+    None,
+    Some("LOP"),
+    None,
+    Some("PC"),
+    None,
+    None,
+    None,
+];
+
+/// DMA RAM selectors, indexed by bits 10-8
+const DMA_RAMS: [Option<&str>; 8] = [
+    Some("M0"),
+    Some("M1"),
+    Some("M2"),
+    Some("M3"),
+    Some("PRG"),
+    None,
+    None,
+    None,
+];
+
+/// Sign extends the low `len` bits of `value`
+fn sign_extend(value: u32, len: u32) -> i32 {
+    let shift = 32 - len;
+    ((value << shift) as i32) >> shift
+}
+
+/// Decodes a condition code (bits 25-19 of JMP and conditional MVI), using the same table the
+/// parser encodes them with
+fn condition(word: u32) -> color_eyre::Result<String> {
+    let bits = word.get_bits(7, 19);
+    match CONDITIONS.iter().find(|(_, encoding)| *encoding == bits) {
+        Some((tok, _)) => Ok(tok.as_ref().to_uppercase()),
+        None => Err(eyre!(
+            "Illegal encoding: Unknown condition code {bits:#09b}"
+        )),
+    }
+}
+
+/// Looks up `index` in a decoding table, erroring if it's a reserved encoding
+fn lookup<const N: usize>(
+    table: &[Option<&'static str>; N],
+    index: u32,
+    what: &str,
+) -> color_eyre::Result<&'static str> {
+    table[index as usize].ok_or_else(|| eyre!("Illegal encoding: Reserved {what} {index:#b}"))
+}
+
+/// Decodes an operation (ALU/bus control) word
+fn operation(word: u32) -> color_eyre::Result<Vec<(InstrType, String)>> {
+    let mut instrs = Vec::new();
+
+    let alu = lookup(&ALU_OPS, word.get_bits(4, 26), "ALU operation")?;
+    if alu != "NOP" {
+        instrs.push((InstrType::Alu, alu.to_string()));
+    }
+
+    // X-bus
+    let x_src = BUS_SOURCES[word.get_bits(3, 20) as usize];
+    if word.is_set(25) {
+        instrs.push((InstrType::XBus, format!("MOV {x_src},X")));
+    }
+    match word.get_bits(2, 23) {
+        0b00 => {}
+        0b10 => instrs.push((InstrType::XBus, "MOV MUL,P".into())),
+        0b11 => instrs.push((InstrType::XBus, format!("MOV {x_src},P"))),
+        _ => return Err(eyre!("Illegal encoding: Reserved P control 0b01")),
+    }
+
+    // Y-bus
+    let y_src = BUS_SOURCES[word.get_bits(3, 14) as usize];
+    if word.is_set(19) {
+        instrs.push((InstrType::YBus, format!("MOV {y_src},Y")));
+    }
+    match word.get_bits(2, 17) {
+        0b00 => {}
+        0b01 => instrs.push((InstrType::YBus, "CLR A".into())),
+        0b10 => instrs.push((InstrType::YBus, "MOV ALU,A".into())),
+        _ => instrs.push((InstrType::YBus, format!("MOV {y_src},A"))),
+    }
+
+    // D1-bus
+    match word.get_bits(2, 12) {
+        0b00 => {}
+        0b01 => {
+            let dest = lookup(&D1_DESTS, word.get_bits(4, 8), "D1-bus destination")?;
+            instrs.push((
+                InstrType::D1Bus,
+                format!("MOV #{},{dest}", sign_extend(word.get_bits(8, 0), 8)),
+            ));
+        }
+        0b11 => {
+            let dest = lookup(&D1_DESTS, word.get_bits(4, 8), "D1-bus destination")?;
+            let src = lookup(&D1_SOURCES, word.get_bits(4, 0), "D1-bus source")?;
+            // the source only uses the low 4 bits of the immediate field
+            reserved(word, 4, 4)?;
+            instrs.push((InstrType::D1Bus, format!("MOV {src},{dest}")));
+        }
+        _ => return Err(eyre!("Illegal encoding: Reserved D1-bus control 0b10")),
+    }
+
+    if instrs.is_empty() {
+        instrs.push((InstrType::Alu, "NOP".into()));
+    }
+
+    Ok(instrs)
+}
+
+/// Errors if any of the `len` bits starting at `shift`, which the instruction doesn't use, are set
+fn reserved(word: u32, len: u32, shift: u32) -> color_eyre::Result<()> {
+    let bits = word.get_bits(len, shift);
+    if bits != 0 {
+        return Err(eyre!(
+            "Illegal encoding: Unused bits {}-{shift} are set ({bits:#b})",
+            shift + len - 1
+        ));
+    }
+
+    Ok(())
+}
+
+/// Decodes a single instruction word into the instructions that make up its bundle, along with
+/// the type of each
+fn decode(word: u32) -> color_eyre::Result<Vec<(InstrType, String)>> {
+    match word.get_bits(2, 30) {
+        0b00 => operation(word),
+        0b10 => {
+            let dest = lookup(&MVI_DESTS, word.get_bits(4, 26), "MVI destination")?;
+            let text = if word.is_set(25) {
+                let imm = sign_extend(word.get_bits(19, 0), 19);
+                format!("MVI #{imm},{dest},{}", condition(word)?)
+            } else {
+                let imm = sign_extend(word.get_bits(25, 0), 25);
+                format!("MVI #{imm},{dest}")
+            };
+            Ok(vec![(InstrType::LoadImm, text)])
+        }
+        0b11 => match word.get_bits(2, 28) {
+            0b00 => {
+                reserved(word, 13, 15)?;
+                reserved(word, 1, 11)?;
+                let mnemonic = if word.is_set(14) { "DMAH" } else { "DMA" };
+                let ram = lookup(&DMA_RAMS, word.get_bits(3, 8), "DMA RAM")?;
+                let count = if word.is_set(13) {
+                    reserved(word, 5, 3)?;
+                    BUS_SOURCES[word.get_bits(3, 0) as usize].to_string()
+                } else {
+                    format!("#{}", word.get_bits(8, 0))
+                };
+                let text = if word.is_set(12) {
+                    format!("{mnemonic} {ram},D0,{count}")
+                } else {
+                    format!("{mnemonic} D0,{ram},{count}")
+                };
+                Ok(vec![(InstrType::Dma, text)])
+            }
+            0b01 => {
+                reserved(word, 11, 8)?;
+                let target = word.get_bits(8, 0);
+                let text = if word.is_set(25) {
+                    format!("JMP {},${target:02X}", condition(word)?)
+                } else {
+                    format!("JMP ${target:02X}")
+                };
+                Ok(vec![(InstrType::FlowControl, text)])
+            }
+            0b10 => {
+                reserved(word, 27, 0)?;
+                let text = if word.is_set(27) { "LPS" } else { "BTM" };
+                Ok(vec![(InstrType::FlowControl, text.into())])
+            }
+            _ => {
+                reserved(word, 27, 0)?;
+                let text = if word.is_set(27) { "ENDI" } else { "END" };
+                Ok(vec![(InstrType::FlowControl, text.into())])
+            }
+        },
+        _ => Err(eyre!("Illegal encoding: Reserved instruction class 0b01")),
+    }
+}
+
+/// Decodes a single instruction word into the instructions that make up its bundle
+pub fn disassemble(word: u32) -> color_eyre::Result<Vec<String>> {
+    Ok(decode(word)?.into_iter().map(|(_, text)| text).collect())
+}
+
+/// Disassembles a word into a single line of text, showing reserved encodings inline rather than
+/// failing
+pub fn disassemble_line(word: u32) -> String {
+    match disassemble(word) {
+        Ok(instrs) => instrs.join("  "),
+        Err(error) => format!("<{error}>"),
+    }
+}
+
+/// Compares two programs word by word, returning a description of each address that differs
+pub fn diff(a: &[u32], b: &[u32]) -> Vec<String> {
+    let mut diffs = Vec::new();
+
+    for addr in 0..a.len().max(b.len()) {
+        let (left, right) = (a.get(addr), b.get(addr));
+        if left == right {
+            continue;
+        }
+
+        let describe = |word: Option<&u32>| match word {
+            Some(word) => format!("{} ({word:#010x})", disassemble_line(*word)),
+            None => "<missing>".into(),
+        };
+        diffs.push(format!(
+            "${addr:02X}: {}  |  {}",
+            describe(left),
+            describe(right)
+        ));
+    }
+
+    diffs
+}
+
+/// Produces a listing of the program with the address, word and disassembled instructions of each
+/// bundle, to check the assembler encoded what was intended
+pub fn listing(words: &[u32], columns: &Columns) -> String {
+    let mut out = String::new();
+
+    for (addr, word) in words.iter().enumerate() {
+        let prefix = format!("${addr:02X}  {word:08X}  ");
+        let instrs = match disassemble(*word) {
+            Ok(instrs) => columns.join(prefix.len(), &instrs),
+            Err(error) => format!("<{error}>"),
+        };
+        out += &format!("{prefix}{instrs}\n");
+    }
+
+    out
+}
+
+/// Turns a program back into source that assembles to the same words, one bundle per line with its
+/// address in a comment. Words that don't decode to a legal bundle are written out with `DW`, so
+/// nothing is lost.
+pub fn source(words: &[u32]) -> String {
+    let mut out = String::new();
+
+    for (addr, word) in words.iter().enumerate() {
+        let (text, note) = match disassemble(*word) {
+            Ok(instrs) => (instrs.join("  "), String::new()),
+            Err(error) => (format!("DW ${word:08X}"), format!(" {error}")),
+        };
+        out += &format!("    {text:<60}; ${addr:02X}{note}\n");
+    }
+
+    out
+}
+
+/// Produces a listing of the program annotated with the estimated cost of each bundle in cycles
+/// and a running total. Bundles that cost more than one cycle are marked with a `*`.
+pub fn profile(words: &[u32], columns: &Columns) -> String {
+    let mut out = String::from("; addr  cycles  total  instructions\n");
+    let mut total = 0;
+
+    for (addr, word) in words.iter().enumerate() {
+        let cost = cycle_cost(*word);
+        total += cost;
+        let marker = if cost > 1 { '*' } else { ' ' };
+        let prefix = format!("${addr:02X}   {cost:>5}{marker} {total:>5}  ");
+        let instrs = match disassemble(*word) {
+            Ok(instrs) => columns.join(prefix.len(), &instrs),
+            Err(error) => format!("<{error}>"),
+        };
+        out += &format!("{prefix}{instrs}\n");
+    }
+
+    out += &format!("; {total} cycles\n");
+    out
+}
+
+/// Summarises a histogram of bundle words (see [`Program::bundle_histogram`]), listing every word
+/// that occurs more than once, most repeated first
+pub fn histogram_report(histogram: &HashMap<u32, u32>) -> String {
+    let total: u32 = histogram.values().sum();
+    let mut out = format!("; {total} bundles, {} unique\n", histogram.len());
+
+    let mut repeated: Vec<(u32, u32)> = histogram
+        .iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(word, count)| (*word, *count))
+        .collect();
+    // ties are broken on the word, so the report is stable
+    repeated.sort_by_key(|(word, count)| (std::cmp::Reverse(*count), *word));
+
+    if !repeated.is_empty() {
+        out += "; count  word      instructions\n";
+    }
+    for (word, count) in repeated {
+        out += &format!("{count:>7}  {word:08X}  {}\n", disassemble_line(word));
+    }
+
+    out
+}
+
+/// Dumps an assembled program one word per entry, with the line it came from, the labels pointing
+/// at it, and each instruction of a bundle decoded on its own line. Jump targets and other
+/// addresses are shown resolved. The format is stable, for comparing against golden files.
+pub fn ir_dump(prog: &Program) -> String {
+    let mut out = String::new();
+    let labels = prog.sorted_labels();
+
+    for (addr, (word, source)) in prog.words().iter().zip(prog.word_sources()).enumerate() {
+        for (label, _) in labels.iter().filter(|(_, at)| *at as usize == addr) {
+            out += &format!("{label}:\n");
+        }
+
+        match source {
+            WordSource::Bundle(line) => {
+                out += &format!("${addr:02X}  line {}  bundle  {word:08X}\n", line + 1);
+                match decode(*word) {
+                    Ok(instrs) => {
+                        for (_, text) in instrs {
+                            out += &format!("    {text}\n");
+                        }
+                    }
+                    Err(error) => out += &format!("    <{error}>\n"),
+                }
+            }
+            WordSource::Data(line) => {
+                out += &format!("${addr:02X}  line {}  data  {word:08X}\n", line + 1)
+            }
+            WordSource::Padding => out += &format!("${addr:02X}  padding  {word:08X}\n"),
+        }
+    }
+
+    out
+}
+
+/// Checks that every word decodes to a legal bundle, returning a description of each address that
+/// doesn't. Catches corrupt or hand-edited binaries.
+pub fn lint(words: &[u32]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (addr, word) in words.iter().enumerate() {
+        let result = decode(*word).and_then(|instrs| {
+            // run the decoded bundle through the same checks the assembler uses
+            let mut prog = Program::default();
+            prog.begin();
+            for (instr_type, _) in instrs {
+                prog.register_emitted(instr_type);
+            }
+            Ok(prog.validate_bundle()?)
+        });
+
+        if let Err(error) = result {
+            problems.push(format!("${addr:02X}: {error} ({word:#010x})"));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{emitter::Program, parser::document, tokeniser::lex};
+
+    fn assemble(doc: &str) -> color_eyre::Result<Program> {
+        let mut tokens = lex(doc);
+        let mut prog = Program::default();
+        document(&mut tokens, &mut prog, false)?;
+        Ok(prog)
+    }
+
+    #[test]
+    fn test_disassemble_bundle() -> color_eyre::Result<()> {
+        let prog = assemble("AD2  MOV MC2,X  MOV MUL,P  MOV M3,Y  MOV ALU,A")?;
+        assert_eq!(
+            disassemble(prog.words()[0])?,
+            vec!["AD2", "MOV MC2,X", "MOV MUL,P", "MOV M3,Y", "MOV ALU,A"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disassemble_flow() -> color_eyre::Result<()> {
+        let prog = assemble("JMP NZ, $10\nMVI #3, LOP\nLPS\nENDI")?;
+        let text: Vec<String> = prog.words().iter().map(|w| disassemble_line(*w)).collect();
+        assert_eq!(text, vec!["JMP NZ,$10", "MVI #3,LOP", "LPS", "ENDI"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_condition_round_trip() -> color_eyre::Result<()> {
+        for (tok, _) in CONDITIONS {
+            let name = tok.as_ref().to_uppercase();
+            let prog = assemble(&format!("loop: JMP {name}, loop\nMVI #5, MC0, {name}\n"))?;
+
+            // the condition name survives disassembly, in the listing too
+            let text: Vec<String> = prog.words().iter().map(|w| disassemble_line(*w)).collect();
+            assert_eq!(
+                text,
+                [format!("JMP {name},$00"), format!("MVI #5,MC0,{name}")]
+            );
+            assert!(listing(prog.words(), &Columns::default()).contains(&format!("JMP {name},")));
+
+            // and reassembling the disassembly gives back the same words
+            assert_eq!(assemble(&(text.join("\n") + "\n"))?.words(), prog.words());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disassemble_reserved() {
+        assert!(disassemble(0x4000_0000).is_err());
+    }
+
+    #[test]
+    fn test_diff_reordered_bundle() -> color_eyre::Result<()> {
+        let a = assemble("CLR A  MOV MC3,X  MOV M3,P\nAD2  MOV MUL,P\n")?;
+        let b = assemble("MOV M3,P  MOV MC3,X  CLR A\nMOV MUL,P  AD2\n")?;
+        assert!(diff(a.words(), b.words()).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_changed_word() -> color_eyre::Result<()> {
+        let a = assemble("CLR A\nAD2\n")?;
+        let b = assemble("CLR A\nSUB\nENDI\n")?;
+        assert_eq!(
+            diff(a.words(), b.words()),
+            vec![
+                "$01: AD2 (0x18000000)  |  SUB (0x14000000)",
+                "$02: <missing>  |  ENDI (0xf8000000)",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_dma() -> color_eyre::Result<()> {
+        let prog = assemble("CLR A\nDMA D0,M0,#4\nMOV MUL,P\n")?;
+        assert_eq!(
+            profile(prog.words(), &Columns::default()),
+            "; addr  cycles  total  instructions\n\
+             $00       1      1  CLR A\n\
+             $01       2*     3  DMA D0,M0,#4\n\
+             $02       1      4  MOV MUL,P\n\
+             ; 4 cycles\n"
+        );
+
+        // instructions in a bundle line up on tab stops counted from the start of the line
+        let prog = assemble("CLR A  MOV MUL,P\n")?;
+        let columns = Columns {
+            tab_width: 8,
+            use_tabs: false,
+        };
+        assert_eq!(
+            profile(prog.words(), &columns),
+            "; addr  cycles  total  instructions\n\
+             $00       1      1  MOV MUL,P   CLR A\n\
+             ; 1 cycles\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing() -> color_eyre::Result<()> {
+        let prog = assemble("CLR A\nAD2  MOV MUL,P\nENDI\n")?;
+        assert_eq!(
+            listing(prog.words(), &Columns::default()),
+            "$00  00020000  CLR A\n\
+             $01  19000000  AD2  MOV MUL,P\n\
+             $02  F8000000  ENDI\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source() -> color_eyre::Result<()> {
+        let prog = assemble("CLR A\nAD2  MOV MUL,P\nENDI\n")?;
+        let mut words = prog.words().to_vec();
+        words.push(0x4000_0000);
+
+        let text = source(&words);
+        let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(lines[0], format!("    {:<60}; $00", "CLR A"));
+        assert_eq!(lines[1], format!("    {:<60}; $01", "AD2  MOV MUL,P"));
+        assert!(lines[3].starts_with("    DW $40000000"));
+        assert!(lines[3].ends_with("; $03 Illegal encoding: Reserved instruction class 0b01"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_round_trip() -> color_eyre::Result<()> {
+        let original = "\
+            start:  MVI #-5,MC0,NZ\n\
+                    AD2  MOV MC2,X  MOV MUL,P  MOV M3,Y  MOV ALU,A  MOV #3,PL\n\
+                    SUB  CLR A  MOV M0,Y\n\
+                    DMAH M2,D0,#8\n\
+                    DMA D0,PRG,M1\n\
+                    MVI #12,LOP\n\
+                    BTM\n\
+                    JMP Z,start\n\
+                    MOV MC1,X  MOV #7,RX\n\
+                    DW $4000_0000\n\
+                    DW $D000_FF10\n\
+                    DW $C001_0000\n\
+                    ENDI\n";
+        let prog = assemble(original)?;
+
+        // a JMP and a DMA with stray reserved bits can't be written as mnemonics without losing
+        // those bits, so they stay as data
+        let text = source(prog.words());
+        assert!(text.contains("DW $D000FF10"));
+        assert!(text.contains("DW $C0010000"));
+
+        // the disassembly assembles back to the same words, and so to the same mnemonics
+        let disassembled = assemble(&text)?;
+        assert_eq!(disassembled.words(), prog.words());
+        for (a, b) in prog.words().iter().zip(disassembled.words()) {
+            assert_eq!(disassemble_line(*a), disassemble_line(*b));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram() -> color_eyre::Result<()> {
+        let prog = assemble("CLR A\nMOV MUL,P\nCLR A\nAD2\nCLR A\nAD2\n")?;
+        let histogram = prog.bundle_histogram();
+
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(histogram[&0x0002_0000], 3);
+        assert_eq!(
+            histogram_report(&histogram),
+            "; 6 bundles, 3 unique\n\
+             ; count  word      instructions\n\
+             \x20     3  00020000  CLR A\n\
+             \x20     2  18000000  AD2\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ir_dump() -> color_eyre::Result<()> {
+        let prog = assemble("ORG 1\nstart: CLR A  MOV MUL,P\nJMP start\n.data\ntable: DW 3\n")?;
+        assert_eq!(
+            ir_dump(&prog),
+            "$00  padding  00000000\n\
+             start:\n\
+             $01  line 2  bundle  01020000\n    \
+                 MOV MUL,P\n    \
+                 CLR A\n\
+             $02  line 3  bundle  D0000001\n    \
+                 JMP $01\n\
+             table:\n\
+             $03  line 5  data  00000003\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lint() -> color_eyre::Result<()> {
+        let prog = assemble("CLR A  MOV MUL,P\nMVI #3,LOP\nLPS\nENDI\n")?;
+        assert!(lint(prog.words()).is_empty());
+
+        // a reserved ALU operation, and an END, JMP and DMAs with stray bits set
+        let words = [
+            0x0002_0000,
+            0x1C00_0000,
+            0xF000_0001,
+            0xD000_FF00,
+            0xC001_0000,
+            0xC000_0800,
+            0xC000_2009,
+        ];
+        assert_eq!(
+            lint(&words),
+            vec![
+                "$01: Illegal encoding: Reserved ALU operation 0b111 (0x1c000000)",
+                "$02: Illegal encoding: Unused bits 26-0 are set (0b1) (0xf0000001)",
+                "$03: Illegal encoding: Unused bits 18-8 are set (0b11111111) (0xd000ff00)",
+                "$04: Illegal encoding: Unused bits 27-15 are set (0b10) (0xc0010000)",
+                "$05: Illegal encoding: Unused bits 11-11 are set (0b1) (0xc0000800)",
+                "$06: Illegal encoding: Unused bits 7-3 are set (0b1) (0xc0002009)",
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -6,10 +6,93 @@
 // was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use color_eyre::eyre::eyre;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
+};
 
 use bit_ops::BitOps;
-use log::{debug, info};
+use clap::ValueEnum;
+use log::{debug, info, warn};
+
+use crate::{
+    output::{self, HexCase},
+    tokeniser::ScuDspToken,
+};
+
+/// Number of banks used by [`Layout::Banked`]
+const LAYOUT_BANKS: usize = 4;
+
+/// How words are arranged in the output file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Words in program order, as loaded into program RAM
+    #[default]
+    Flat,
+
+    /// Words interleaved across four banks: word n goes to bank n % 4 at offset n / 4, with each
+    /// bank padded to the same length (see [`PadPolicy`]) and written one after another
+    Banked,
+}
+
+/// What goes in words that are only there as padding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PadPolicy {
+    /// All zeroes, which is a NOP bundle
+    #[default]
+    Nop,
+
+    /// ENDI, which stops the DSP and raises its end interrupt, so a program that runs off into the
+    /// padding halts straight away and the host finds out
+    Trap,
+
+    /// An arbitrary word
+    Value(u32),
+}
+
+impl PadPolicy {
+    /// Word `ENDI` encodes to, used by [`PadPolicy::Trap`]
+    pub const TRAP_WORD: u32 = 0xF800_0000;
+
+    /// Returns the word padding is filled with
+    pub fn word(self) -> u32 {
+        match self {
+            PadPolicy::Nop => 0,
+            PadPolicy::Trap => Self::TRAP_WORD,
+            PadPolicy::Value(value) => value,
+        }
+    }
+
+    /// Parses a policy given on the command line: `nop`, `trap` or `value:N`
+    pub fn parse(value: &str) -> Result<PadPolicy, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "nop" => Ok(PadPolicy::Nop),
+            "trap" => Ok(PadPolicy::Trap),
+            _ => match value.split_once(':') {
+                Some((kind, word)) if kind.eq_ignore_ascii_case("value") => {
+                    output::parse_u32(word).map(PadPolicy::Value)
+                }
+                _ => Err(format!(
+                    "'{value}' is not a pad policy; expected nop, trap or value:N"
+                )),
+            },
+        }
+    }
+}
+
+/// Where a word of the program came from, so it can be traced back to the source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordSource {
+    /// A bundle starting on the given line (from 0)
+    Bundle(u32),
+
+    /// A data word declared on the given line (from 0)
+    Data(u32),
+
+    /// Padding inserted to reach an ORG address or reserved with `.space`
+    Padding,
+}
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum InstrType {
@@ -18,6 +101,258 @@ pub enum InstrType {
     YBus,
     D1Bus,
     FlowControl,
+    LoadImm,
+    Dma,
+}
+
+impl fmt::Display for InstrType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InstrType::Alu => "ALU",
+            InstrType::XBus => "X-Bus",
+            InstrType::YBus => "Y-Bus",
+            InstrType::D1Bus => "D1-Bus",
+            InstrType::FlowControl => "flow control",
+            InstrType::LoadImm => "MVI",
+            InstrType::Dma => "DMA",
+        })
+    }
+}
+
+/// Byte order of each word in the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    #[default]
+    Little,
+}
+
+impl Endian {
+    /// Serialises words into bytes. Every output format goes through here, so endianness is
+    /// handled in one place.
+    pub fn words_to_bytes(self, words: &[u32]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|word| match self {
+                Endian::Big => word.to_be_bytes(),
+                Endian::Little => word.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    /// Deserialises bytes back into words, the inverse of [`Endian::words_to_bytes`]
+    pub fn bytes_to_words(self, bytes: &[u8]) -> color_eyre::Result<Vec<u32>> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(eyre!(
+                "Error: Binary is {} bytes, which isn't a whole number of words",
+                bytes.len()
+            ));
+        }
+
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|chunk| {
+                let chunk = chunk.try_into().unwrap();
+                match self {
+                    Endian::Big => u32::from_be_bytes(chunk),
+                    Endian::Little => u32::from_le_bytes(chunk),
+                }
+            })
+            .collect())
+    }
+}
+
+/// Limits on how many instructions can be packed into a single bundle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BundleLimits {
+    pub alu: u32,
+    pub xbus: u32,
+    pub ybus: u32,
+    pub d1bus: u32,
+    pub flow_control: u32,
+
+    /// Total instructions of any type
+    pub total: u32,
+}
+
+impl Default for BundleLimits {
+    /// The limits observed in real world programs, see validate_bundle() for the discussion
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            xbus: 2,
+            ybus: 2,
+            d1bus: 1,
+            flow_control: 1,
+            total: BundleLimits::MAX_TOTAL,
+        }
+    }
+}
+
+impl BundleLimits {
+    /// Most instructions the hardware can fit in a bundle: ALU, two X-bus, two Y-bus and D1-bus
+    pub const MAX_TOTAL: u32 = 6;
+}
+
+/// Registers written by the X-bus and Y-bus instructions of a bundle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovDestination {
+    X,
+    P,
+    Y,
+    A,
+}
+
+impl fmt::Display for MovDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MovDestination::X => "X",
+            MovDestination::P => "P",
+            MovDestination::Y => "Y",
+            MovDestination::A => "A",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Reasons a bundle can't be assembled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssembleError {
+    /// The bundle contains more instructions of one type than allowed
+    TooMany {
+        instr_type: InstrType,
+        count: u32,
+        limit: u32,
+    },
+
+    /// The bundle contains more instructions in total than allowed
+    TooManyTotal { count: u32, limit: u32 },
+
+    /// An instruction that must be on its own shares its bundle with others
+    NotAlone { mnemonic: String },
+
+    /// More than one instruction in the bundle writes to the same register
+    SameDestination { dest: MovDestination },
+
+    /// The source could not be parsed
+    Syntax(String),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::TooMany {
+                instr_type, limit, ..
+            } => {
+                if *limit == 1 {
+                    write!(
+                        f,
+                        "Illegal program: Bundle contains more than one {instr_type} instruction"
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Illegal program: Bundle contains more than {limit} {instr_type} instructions"
+                    )
+                }
+            }
+            AssembleError::TooManyTotal { limit, .. } => write!(
+                f,
+                "Illegal program: More than {limit} instructions issued in a single bundle"
+            ),
+            AssembleError::NotAlone { mnemonic } => write!(
+                f,
+                "Illegal program: {mnemonic} cannot be combined with other instructions in a bundle"
+            ),
+            AssembleError::SameDestination { dest } => write!(
+                f,
+                "Illegal program: Bundle writes to {dest} more than once; the encodings would be \
+                ORed together"
+            ),
+            AssembleError::Syntax(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Estimates how many cycles a single instruction word takes to execute. Most bundles take one
+/// cycle; DMA and the loop instructions (BTM/LPS) are counted as two, since they tie up the DSP for
+/// an extra cycle setting up the transfer or loop. These are estimates for budgeting, not exact
+/// timings.
+pub fn cycle_cost(word: u32) -> u32 {
+    match word.get_bits(4, 28) {
+        // DMA/DMAH
+        0b1100 => 2,
+        // BTM/LPS
+        0b1110 => 2,
+        _ => 1,
+    }
+}
+
+/// Matches a name against a simple pattern, where `*` matches any run of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+/// A non-fatal problem found while assembling
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    /// Line the warning was raised on, starting at 0
+    pub line: u32,
+    pub message: String,
+}
+
+/// A region of program RAM. Code and data are assembled separately, each with its own pc, and the
+/// data section is laid out after the code by [`Program::finalise`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Section {
+    #[default]
+    Code,
+    Data,
+}
+
+/// The value of a define
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Define {
+    /// A plain number
+    Value(u32),
+
+    /// Another define, resolved lazily when used
+    Alias(String),
+
+    /// A register or other operand token, e.g. `SRC EQU M2`
+    Token(ScuDspToken),
+}
+
+/// A word that holds the address of a label, either patched in later (if the address wasn't known
+/// when the word was emitted) or kept to re-patch when the program is rebased
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Relocation {
+    /// Index of the word to patch
+    index: usize,
+
+    /// Label being referenced
+    label: String,
+
+    /// Largest address that fits in the immediate field
+    max: u32,
+
+    /// Line the reference was made on
+    line: u32,
+
+    /// Section the referencing word is in
+    section: Section,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -25,7 +360,8 @@ pub struct Program {
     /// Program code, vector of 32-bit words
     prog: Vec<u32>,
 
-    /// Current position in prog vec
+    /// Current position in prog vec. Program RAM is word addressed, so this counts words not
+    /// bytes.
     pc: u32,
 
     /// Mapping between labels and PC
@@ -43,11 +379,116 @@ pub struct Program {
     /// Counts for each instruction type that was emitted
     instr_type_counts: HashMap<InstrType, u32>,
 
+    /// Registers written by the X-bus and Y-bus instructions of the current bundle, in order
+    destinations: Vec<MovDestination>,
+
     /// Defined constants and their values
-    defines: HashMap<String, u32>,
+    defines: HashMap<String, Define>,
 
     /// Current line, starting at 0
-    pub line: u32
+    pub line: u32,
+
+    /// Columns (starting at 0) of the token on `line` that the last syntax error points at. Errors
+    /// about a whole bundle don't set this.
+    pub column: Option<Range<u32>>,
+
+    /// Warnings raised so far
+    warnings: Vec<Warning>,
+
+    /// True if LOP has been loaded since the last loop instruction
+    lop_loaded: bool,
+
+    /// Total number of instructions committed across all bundles
+    instructions: u32,
+
+    /// Label references waiting to be resolved at the end of assembly
+    relocations: Vec<Relocation>,
+
+    /// Every word that holds the address of a label, for [`Program::rebase`]
+    references: Vec<Relocation>,
+
+    /// True once [`Program::finalise`] has completed
+    finalised: bool,
+
+    /// Limits each bundle is validated against
+    limits: BundleLimits,
+
+    /// If set, instructions may not be emitted until an ORG directive has been seen
+    require_org: bool,
+
+    /// True once an ORG directive has been seen
+    org_seen: bool,
+
+    /// If set, each line may hold at most one instruction other than flow control
+    one_instr_per_line: bool,
+
+    /// Line the last instruction checked by [`Program::check_one_instr_per_line`] was on
+    last_instr_line: Option<u32>,
+
+    /// If set, relocations are logged as they're recorded and patched
+    trace_relocations: bool,
+
+    /// Log of relocations recorded and patched, if tracing
+    relocation_trace: Vec<String>,
+
+    /// Section currently being assembled into
+    section: Section,
+
+    /// Words assembled into the data section
+    data: Vec<u32>,
+
+    /// The pc of whichever section isn't currently active
+    inactive_pc: u32,
+
+    /// Which section each label was declared in
+    label_sections: HashMap<String, Section>,
+
+    /// If set, warn about instructions in the delay slot after a JMP
+    warn_delay_slot: bool,
+
+    /// True if the last bundle committed to the code section was a JMP
+    after_jmp: bool,
+
+    /// Stack of enclosing IF blocks, true if that block's condition held
+    conditionals: Vec<bool>,
+
+    /// Line each label was declared on
+    label_lines: HashMap<String, u32>,
+
+    /// Labels that have been referenced by an instruction
+    referenced_labels: HashSet<String>,
+
+    /// If set, warn about labels that are never referenced
+    warn_unused_labels: bool,
+
+    /// Labels matching these patterns are assumed to be entry points, and never warned about
+    entry_patterns: Vec<String>,
+
+    /// Label execution starts at, if given explicitly
+    entry_label: Option<String>,
+
+    /// Most words the program may grow to before assembly is aborted, if limited
+    word_limit: Option<u32>,
+
+    /// Fill used wherever the output needs padding
+    pad_policy: PadPolicy,
+
+    /// Where each word of the code section came from. The data section's are appended by
+    /// [`Program::finalise`].
+    sources: Vec<WordSource>,
+
+    /// Where each word of the data section came from
+    data_sources: Vec<WordSource>,
+
+    /// Line the current bundle started on
+    bundle_line: u32,
+
+    /// If set, the bundle limit was given on the command line and `.maxbundle` is ignored
+    max_bundle_override: bool,
+
+    /// Defines carried over from a previous pass, which may each be declared once more with the
+    /// same value
+    carried_defines: HashSet<String>,
 }
 
 impl Program {
@@ -63,14 +504,287 @@ impl Program {
         }
     }
 
+    /// Sets the limits each bundle is validated against
+    pub fn set_limits(&mut self, limits: BundleLimits) {
+        self.limits = limits;
+    }
+
+    /// Errors unless `total` is a possible number of instructions in a bundle
+    fn check_max_bundle(total: u32) -> color_eyre::Result<()> {
+        if !(1..=BundleLimits::MAX_TOTAL).contains(&total) {
+            return Err(eyre!(
+                "Error: Bundle limit {total} is out of range (must be 1-{})",
+                BundleLimits::MAX_TOTAL
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Caps the total instructions per bundle from the command line. This takes precedence over
+    /// any `.maxbundle` in the source.
+    pub fn set_max_bundle_override(&mut self, total: Option<u32>) -> color_eyre::Result<()> {
+        if let Some(total) = total {
+            Self::check_max_bundle(total)?;
+            self.limits.total = total;
+        }
+        self.max_bundle_override = total.is_some();
+
+        Ok(())
+    }
+
+    /// Caps the total instructions per bundle from a `.maxbundle` pragma in the source, unless it
+    /// was already set on the command line
+    pub fn set_max_bundle(&mut self, total: u32) -> color_eyre::Result<()> {
+        Self::check_max_bundle(total)?;
+        if self.max_bundle_override {
+            self.add_warning(format!(
+                ".maxbundle {total} ignored, as the limit was set on the command line"
+            ));
+        } else {
+            self.limits.total = total;
+        }
+
+        Ok(())
+    }
+
+    /// Requires an ORG directive before the first instruction
+    pub fn set_require_org(&mut self, require_org: bool) {
+        self.require_org = require_org;
+    }
+
+    /// Moves the code section to `addr` for an ORG directive. Program RAM is always loaded from
+    /// address 0, so rather than changing where the output starts, the gap up to `addr` is filled
+    /// with padding (see [`PadPolicy`]). ORG can only move forwards.
+    pub fn org(&mut self, addr: u32) -> color_eyre::Result<()> {
+        self.ensure_not_emitting();
+        if self.section != Section::Code {
+            return Err(eyre!("Error: ORG can only be used in the code section"));
+        }
+        if addr as usize >= output::PROGRAM_RAM_WORDS {
+            return Err(eyre!(
+                "Error: ORG ${addr:02X} is outside the {} words of program RAM",
+                output::PROGRAM_RAM_WORDS
+            ));
+        }
+        if addr < self.pc {
+            return Err(eyre!(
+                "Error: ORG ${addr:02X} is behind the current address ${:02X}; ORG can't move \
+                backwards",
+                self.pc
+            ));
+        }
+
+        let fill = self.pad_policy.word();
+        self.prog.resize(addr as usize, fill);
+        self.sources.resize(addr as usize, WordSource::Padding);
+        self.set_pc(addr);
+        self.org_seen = true;
+
+        self.check_word_limit()
+    }
+
+    /// Allows at most one instruction (other than flow control) on each line, so bundles have to be
+    /// built up with line continuations
+    pub fn set_one_instr_per_line(&mut self, one_instr_per_line: bool) {
+        self.one_instr_per_line = one_instr_per_line;
+    }
+
+    /// Errors if an instruction is about to be emitted on a line that already has one, when
+    /// limited to one instruction per line. Flow control instructions aren't counted.
+    pub fn check_one_instr_per_line(&mut self, flow_control: bool) -> color_eyre::Result<()> {
+        if !self.one_instr_per_line || flow_control {
+            return Ok(());
+        }
+
+        if self.last_instr_line == Some(self.line) {
+            return Err(eyre!(
+                "Illegal program: More than one instruction on a line (required by \
+                --one-instr-per-line); put each on its own line and join the bundle with '\\'"
+            ));
+        }
+        self.last_instr_line = Some(self.line);
+
+        Ok(())
+    }
+
+    /// Errors if an instruction is about to be emitted before a required ORG directive
+    pub fn check_org(&self) -> color_eyre::Result<()> {
+        if self.require_org && !self.org_seen {
+            return Err(eyre!(
+                "Error: Instruction issued before ORG. Set the load address with ORG first \
+                (required by --require-org)"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Logs each relocation as it's recorded and patched
+    pub fn set_trace_relocations(&mut self, trace_relocations: bool) {
+        self.trace_relocations = trace_relocations;
+    }
+
+    /// Warns when the bundle after a JMP, which executes before the jump takes effect, isn't a NOP
+    pub fn set_warn_delay_slot(&mut self, warn_delay_slot: bool) {
+        self.warn_delay_slot = warn_delay_slot;
+    }
+
+    /// Warns about labels that are never referenced, other than the first label and those
+    /// matching one of `entry_patterns` (where `*` matches anything)
+    pub fn set_warn_unused_labels(
+        &mut self,
+        warn_unused_labels: bool,
+        entry_patterns: Vec<String>,
+    ) {
+        self.warn_unused_labels = warn_unused_labels;
+        self.entry_patterns = entry_patterns;
+    }
+
+    /// Sets the label execution starts at. It's checked to exist by [`Program::finalise`].
+    pub fn set_entry(&mut self, entry_label: Option<String>) {
+        self.entry_label = entry_label;
+    }
+
+    /// Caps how many words (code and data) the program may contain, aborting assembly once it's
+    /// exceeded. This is a guard against runaway input when assembling untrusted source, separate
+    /// from the size of program RAM.
+    pub fn set_word_limit(&mut self, word_limit: Option<u32>) {
+        self.word_limit = word_limit;
+    }
+
+    /// Sets what padding in the output is filled with
+    pub fn set_pad_policy(&mut self, pad_policy: PadPolicy) {
+        self.pad_policy = pad_policy;
+    }
+
+    /// Returns what padding in the output is filled with
+    pub fn pad_policy(&self) -> PadPolicy {
+        self.pad_policy
+    }
+
+    /// Errors if the program has grown past its word limit
+    fn check_word_limit(&self) -> color_eyre::Result<()> {
+        if let Some(limit) = self.word_limit
+            && self.prog.len() + self.data.len() > limit as usize
+        {
+            return Err(eyre!(
+                "Error: Program exceeds the limit of {limit} words set by --limit-words"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the label (if any) and address execution starts at. Unless set explicitly, this is
+    /// the first label in the program, or address 0 if there are no labels.
+    pub fn entry(&self) -> (Option<&str>, u32) {
+        if let Some(label) = &self.entry_label
+            && let Some(addr) = self.labels.get(label)
+        {
+            return (Some(label), *addr);
+        }
+
+        match self.sorted_labels().first() {
+            Some((label, addr)) => (Some(label), *addr),
+            None => (None, 0),
+        }
+    }
+
+    /// Records a relocation trace message, if tracing
+    fn trace(&mut self, message: String) {
+        if self.trace_relocations {
+            info!("{}", message);
+            self.relocation_trace.push(message);
+        }
+    }
+
+    /// Returns the relocation trace (empty unless tracing was enabled)
+    pub fn relocation_trace(&self) -> &[String] {
+        &self.relocation_trace
+    }
+
+    /// Words of the section currently being assembled into
+    fn section_words(&mut self) -> &mut Vec<u32> {
+        match self.section {
+            Section::Code => &mut self.prog,
+            Section::Data => &mut self.data,
+        }
+    }
+
+    /// Commits a word to the current section, recording where it came from
+    fn push_word(&mut self, word: u32, source: WordSource) {
+        self.section_words().push(word);
+        match self.section {
+            Section::Code => self.sources.push(source),
+            Section::Data => self.data_sources.push(source),
+        }
+    }
+
+    /// Returns the section currently being assembled into
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// Switches to assembling into another section, picking up where it left off. The current
+    /// bundle must have been flushed.
+    pub fn set_section(&mut self, section: Section) {
+        self.ensure_not_emitting();
+        if section != self.section {
+            std::mem::swap(&mut self.pc, &mut self.inactive_pc);
+            self.section = section;
+        }
+    }
+
+    /// Emits a data word directly, outside of any bundle
+    pub fn emit_data(&mut self, value: u32) -> color_eyre::Result<()> {
+        self.ensure_not_emitting();
+        self.push_word(value, WordSource::Data(self.line));
+        self.pc += 1;
+
+        self.check_word_limit()
+    }
+
+    /// Reserves `count` words in the current section for `.space`, filled with padding (see
+    /// [`PadPolicy`])
+    pub fn space(&mut self, count: u32) -> color_eyre::Result<()> {
+        self.ensure_not_emitting();
+        if count as usize > output::PROGRAM_RAM_WORDS {
+            return Err(eyre!(
+                "Error: .space {count} is larger than the {} words of program RAM",
+                output::PROGRAM_RAM_WORDS
+            ));
+        }
+
+        let fill = self.pad_policy.word();
+        for _ in 0..count {
+            self.push_word(fill, WordSource::Padding);
+        }
+        self.pc += count;
+
+        self.check_word_limit()
+    }
+
+    /// Returns the section a label was declared in
+    pub fn label_section(&self, label: &str) -> Option<Section> {
+        self.label_sections.get(label).copied()
+    }
+
     /// Starts emitting a new bundle
     pub fn begin(&mut self) {
         debug!("Begin new bundle");
         self.ensure_not_emitting();
         self.word = 0;
         self.emitted = 0;
+        self.bundle_line = self.line;
         self.is_emitting = true;
         self.instr_type_counts.clear();
+        self.destinations.clear();
+    }
+
+    /// Returns true if a bundle has been started but not yet flushed
+    pub fn is_emitting(&self) -> bool {
+        self.is_emitting
     }
 
     pub fn begin_if_not_begun(&mut self) {
@@ -86,6 +800,12 @@ impl Program {
         self.emitted += 1;
     }
 
+    /// Adds operand bits to the current bundle, without counting them as a new instruction
+    pub fn emit_operand(&mut self, bits: u32) {
+        self.ensure_emitting();
+        self.word |= bits;
+    }
+
     /// Adds a single bit to the current bundle
     pub fn emit_bit(&mut self, bit: u32) {
         self.ensure_emitting();
@@ -111,29 +831,40 @@ impl Program {
         }
     }
 
+    /// Registers with the emitter that an instruction in the current bundle writes to a register
+    pub fn register_destination(&mut self, dest: MovDestination) {
+        self.destinations.push(dest);
+    }
+
+    /// Returns how many instructions of the given type are in the current bundle
+    fn count(&self, instr_type: InstrType) -> u32 {
+        self.instr_type_counts
+            .get(&instr_type)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Checks a per-type limit for the current bundle
+    fn check_limit(&self, instr_type: InstrType, limit: u32) -> Result<(), AssembleError> {
+        let count = self.count(instr_type);
+        if count > limit {
+            return Err(AssembleError::TooMany {
+                instr_type,
+                count,
+                limit,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Validates the current bundle
-    fn validate_bundle(&self) -> color_eyre::Result<()> {
+    pub fn validate_bundle(&self) -> Result<(), AssembleError> {
         // ensure only one flow control (JMP, BTM/LOOP, etc)
-        if self
-            .instr_type_counts
-            .get(&InstrType::FlowControl)
-            .is_some_and(|it| *it > 1)
-        {
-            return Err(eyre!(
-                "Illegal program: Bundle contains more than one flow control instruction"
-            ));
-        }
+        self.check_limit(InstrType::FlowControl, self.limits.flow_control)?;
 
         // ensure only one ALU instr per bundle
-        if self
-            .instr_type_counts
-            .get(&InstrType::Alu)
-            .is_some_and(|it| *it > 1)
-        {
-            return Err(eyre!(
-                "Illegal program: Bundle contains more than one ALU instruction"
-            ));
-        }
+        self.check_limit(InstrType::Alu, self.limits.alu)?;
 
         // So, here's where things get interesting. In the manual, pp. 91 (PDF page 107) it very
         // clear states that only 4 instructions can be issued in a bundle. However, real world
@@ -147,39 +878,66 @@ impl Program {
         // coded, and hence you can issue multiple X-Bus/Y-Bus instructions in a single bundle
         // without problems.
         //
-        // So, for SoCUte, we allow 2 X-Bus and 2 Y-Bus instructions per bundle. D1-BUS TBA.
+        // So, for SoCUte, we allow 2 X-Bus and 2 Y-Bus instructions per bundle by default (see
+        // BundleLimits). The D1 bus isn't one-hot: its source, destination and control share bits
+        // 13-0, so there's room for just one D1-Bus instruction.
+        self.check_limit(InstrType::XBus, self.limits.xbus)?;
+        self.check_limit(InstrType::YBus, self.limits.ybus)?;
+        self.check_limit(InstrType::D1Bus, self.limits.d1bus)?;
 
-        if self
-            .instr_type_counts
-            .get(&InstrType::XBus)
-            .is_some_and(|it| *it > 2)
-        {
-            return Err(eyre!(
-                "Illegal program: Bundle contains more than 2 X-Bus instructions"
-            ));
+        // each register has a single field in the word, so two writes to it would clobber each
+        // other's bits
+        for (i, dest) in self.destinations.iter().enumerate() {
+            if self.destinations[..i].contains(dest) {
+                return Err(AssembleError::SameDestination { dest: *dest });
+            }
         }
 
-        if self
-            .instr_type_counts
-            .get(&InstrType::YBus)
-            .is_some_and(|it| *it > 2)
-        {
-            return Err(eyre!(
-                "Illegal program: Bundle contains more than 2 Y-Bus instructions"
-            ));
+        let total = self.instr_type_counts.values().sum::<u32>();
+
+        // MVI, DMA and flow control (JMP, loops and END) use the whole instruction word, so they
+        // can't share a bundle with anything. Unlike the operation instructions, these have no ALU
+        // or bus fields (see the instruction formats in the SCU manual).
+        for (instr_type, mnemonic) in [
+            (InstrType::LoadImm, "MVI"),
+            (InstrType::Dma, "DMA"),
+            (InstrType::FlowControl, "JMP/BTM/LPS/END/ENDI"),
+        ] {
+            if self.count(instr_type) > 0 && total > 1 {
+                return Err(AssembleError::NotAlone {
+                    mnemonic: mnemonic.into(),
+                });
+            }
         }
 
-        // finally, let's also check to make sure they're not issuing more than 6 instructions per
+        // finally, let's also check to make sure they're not issuing too many instructions per
         // bundle
-        if self.instr_type_counts.values().sum::<u32>() > 6 {
-            return Err(eyre!(
-                "Illegal program: More than 6 instructions issued in a single bundle"
-            ));
+        if total > self.limits.total {
+            return Err(AssembleError::TooManyTotal {
+                count: total,
+                limit: self.limits.total,
+            });
         }
 
         Ok(())
     }
 
+    /// Throws away the current bundle without committing it
+    pub fn discard(&mut self) {
+        debug!("Discard bundle");
+        let committed = self.section_words().len();
+        let section = self.section;
+        self.relocations
+            .retain(|it| it.section != section || it.index < committed);
+        self.references
+            .retain(|it| it.section != section || it.index < committed);
+        self.is_emitting = false;
+        self.word = 0;
+        self.emitted = 0;
+        self.instr_type_counts.clear();
+        self.destinations.clear();
+    }
+
     /// Flushes and commits the current bundle
     pub fn flush(&mut self) -> color_eyre::Result<()> {
         debug!("Finalise bundle");
@@ -190,8 +948,14 @@ impl Program {
             // if we have instructions in the bundle, we better validate the bundle
             self.validate_bundle()?;
 
-            self.prog.push(self.word);
-            self.pc += 4; // sizeof(uint32)
+            let word = self.word;
+            if self.section == Section::Code {
+                self.check_delay_slot(word);
+            }
+            self.push_word(word, WordSource::Bundle(self.bundle_line));
+            self.pc += 1;
+            self.instructions += self.emitted;
+            self.check_word_limit()?;
         }
         debug!("Flushed {} instructions to bundle", self.emitted);
 
@@ -199,15 +963,294 @@ impl Program {
         self.word = 0;
         self.emitted = 0;
         self.instr_type_counts.clear();
+        self.destinations.clear();
 
         Ok(())
     }
 
+    /// Checks the bundle about to be committed to the code section against the delay slot of a
+    /// preceding JMP. The SCU DSP fetches the next bundle while a JMP executes, so that bundle
+    /// always runs before the jump takes effect, whether or not the jump is taken.
+    fn check_delay_slot(&mut self, word: u32) {
+        if self.warn_delay_slot && self.after_jmp {
+            if self.count(InstrType::FlowControl) > 0 {
+                self.add_warning(
+                    "Flow control instruction in the delay slot of a JMP; the behaviour is \
+                    undefined"
+                        .into(),
+                );
+            } else if word != 0 {
+                self.add_warning(
+                    "Bundle in the delay slot of a JMP runs before the jump takes effect; use a \
+                    NOP if this isn't intended"
+                        .into(),
+                );
+            }
+        }
+
+        self.after_jmp = word & 0xF000_0000 == 0xD000_0000;
+    }
+
     pub fn add_label(&mut self, label: String) {
+        self.label_sections.insert(label.clone(), self.section);
+        self.label_lines.insert(label.clone(), self.line);
         self.labels.insert(label, self.pc);
     }
 
-    pub fn add_define(&mut self, constant: String, value: u32) -> color_eyre::Result<()> {
+    /// Fills the low bits of the current bundle with the address of a label. If the label hasn't
+    /// been declared yet, this is deferred until [`Program::finalise`]. `max` is the largest
+    /// address that fits in the immediate field.
+    pub fn reference_label(&mut self, label: String, max: u32) -> color_eyre::Result<()> {
+        self.ensure_emitting();
+        if let Some(addr) = self.label_address(label, max)? {
+            self.word |= addr;
+        }
+
+        Ok(())
+    }
+
+    /// Emits a data word holding the address of a label, for jump tables. Like
+    /// [`Program::reference_label`], this is deferred if the address isn't known yet. Addresses
+    /// are in words, the same as JMP targets.
+    pub fn emit_data_label(&mut self, label: String) -> color_eyre::Result<()> {
+        self.ensure_not_emitting();
+        let addr = self.label_address(label, u32::MAX)?;
+        self.emit_data(addr.unwrap_or(0))
+    }
+
+    /// Returns the address of a label referenced by the next word committed to the current
+    /// section, or records a relocation for [`Program::finalise`] to patch and returns `None`
+    fn label_address(&mut self, label: String, max: u32) -> color_eyre::Result<Option<u32>> {
+        self.referenced_labels.insert(label.clone());
+
+        // the word referencing the label is committed after every word before it
+        let reference = Relocation {
+            index: self.section_words().len(),
+            label: label.clone(),
+            max,
+            line: self.line,
+            section: self.section,
+        };
+        self.references.push(reference.clone());
+
+        // data labels move when the data section is laid out, so those are always deferred
+        if let Some(addr) = self.labels.get(&label)
+            && self.label_section(&label) != Some(Section::Data)
+        {
+            if *addr > max {
+                return Err(eyre!(
+                    "Error: Address of label '{label}' ({addr}) will not fit in the immediate \
+                    field (max {max})"
+                ));
+            }
+            debug!("Resolve label: '{}' -> {}", label, addr);
+            return Ok(Some(*addr));
+        }
+
+        debug!("Defer reference to label '{}'", label);
+        self.trace(format!(
+            "Relocation: word ${:02X} references '{}' (line {})",
+            reference.index,
+            reference.label,
+            self.line + 1
+        ));
+        self.relocations.push(reference);
+
+        Ok(None)
+    }
+
+    /// Enters an IF block
+    pub fn push_conditional(&mut self, condition: bool) {
+        self.conditionals.push(condition);
+    }
+
+    /// Leaves the innermost IF block
+    pub fn pop_conditional(&mut self) -> color_eyre::Result<()> {
+        if self.conditionals.pop().is_none() {
+            return Err(eyre!("Syntax error: ENDIF without a matching IF"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if we're inside an IF block whose condition didn't hold, so source should be
+    /// skipped
+    pub fn is_skipping(&self) -> bool {
+        self.conditionals.contains(&false)
+    }
+
+    /// Resolves label references that were made before the label was declared. Must be called
+    /// once the whole document has been parsed.
+    ///
+    /// Label addresses are final once this returns, and the symbol table, summary and listings
+    /// are all built from them afterwards. Anything that moves words around (such as placing the
+    /// data section after the code) has to happen in here, before relocations are patched.
+    pub fn finalise(&mut self) -> color_eyre::Result<()> {
+        if !self.conditionals.is_empty() {
+            return Err(eyre!(
+                "Syntax error: {} IF block(s) not closed with ENDIF",
+                self.conditionals.len()
+            ));
+        }
+
+        // make sure every define actually resolves, even if it was never used
+        let mut names: Vec<_> = self.defines.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            self.resolve(name)?;
+        }
+
+        // lay the data section out after the code, moving its labels along with it
+        self.set_section(Section::Code);
+        let code_len = self.prog.len();
+        for (label, addr) in self.labels.iter_mut() {
+            if self.label_sections.get(label) == Some(&Section::Data) {
+                *addr += code_len as u32;
+            }
+        }
+        self.prog.append(&mut self.data);
+        self.sources.append(&mut self.data_sources);
+        for reference in self.references.iter_mut() {
+            if reference.section == Section::Data {
+                reference.index += code_len;
+                reference.section = Section::Code;
+            }
+        }
+
+        for mut reloc in std::mem::take(&mut self.relocations) {
+            if reloc.section == Section::Data {
+                reloc.index += code_len;
+            }
+
+            // point error context at the reference, not the end of the file
+            self.line = reloc.line;
+
+            let Some(addr) = self.labels.get(&reloc.label) else {
+                return Err(eyre!(
+                    "Symbol '{}' is not a declared define or label",
+                    reloc.label
+                ));
+            };
+            if *addr > reloc.max {
+                return Err(eyre!(
+                    "Error: Address of label '{}' ({}) will not fit in the immediate field (max {})",
+                    reloc.label,
+                    addr,
+                    reloc.max
+                ));
+            }
+
+            debug!("Resolve forward label: '{}' -> {}", reloc.label, addr);
+            let addr = *addr;
+            self.prog[reloc.index] |= addr;
+            self.trace(format!(
+                "Patched: '{}' -> ${:02X}, word ${:02X} = {:#010x}",
+                reloc.label, addr, reloc.index, self.prog[reloc.index]
+            ));
+        }
+
+        if let Some(label) = &self.entry_label
+            && !self.labels.contains_key(label)
+        {
+            return Err(eyre!(
+                "Error: Entry point '{label}' is not a declared label"
+            ));
+        }
+
+        if self.warn_unused_labels {
+            self.check_unused_labels();
+        }
+
+        self.finalised = true;
+        Ok(())
+    }
+
+    /// Relocates a finalised program to be loaded `offset` words further on (or back, if
+    /// negative), for linking it with others. Every label moves by the offset, and every word
+    /// holding a label's address (JMP targets, MVI immediates, DW tables) is re-patched to match.
+    /// Nothing changes if any address would end up out of range.
+    pub fn rebase(&mut self, offset: i64) -> color_eyre::Result<()> {
+        if !self.finalised {
+            return Err(eyre!(
+                "Error: A program can only be rebased once it has been finalised"
+            ));
+        }
+
+        let moved = |addr: u32| i64::from(addr) + offset;
+
+        // check everything first, so a failed rebase leaves the program as it was
+        for (label, addr) in self.sorted_labels() {
+            if u32::try_from(moved(addr)).is_err() {
+                return Err(eyre!(
+                    "Error: Rebasing by {offset} moves label '{label}' to {}, which is out of \
+                    range",
+                    moved(addr)
+                ));
+            }
+        }
+        for reference in &self.references {
+            let addr = moved(self.labels[&reference.label]);
+            if addr > i64::from(reference.max) {
+                self.line = reference.line;
+                return Err(eyre!(
+                    "Error: Rebasing by {offset} moves label '{}' to {addr}, which will not fit \
+                    in the immediate field (max {})",
+                    reference.label,
+                    reference.max
+                ));
+            }
+        }
+        if u32::try_from(moved(self.pc)).is_err() {
+            return Err(eyre!(
+                "Error: Rebasing by {offset} moves the program out of range"
+            ));
+        }
+
+        for addr in self.labels.values_mut() {
+            *addr = moved(*addr) as u32;
+        }
+        // every field is a power of two wide, so its largest value masks out the old address
+        for reference in &self.references {
+            let word = &mut self.prog[reference.index];
+            *word = (*word & !reference.max) | self.labels[&reference.label];
+        }
+        self.pc = moved(self.pc) as u32;
+
+        Ok(())
+    }
+
+    /// Warns about every label that was never referenced
+    fn check_unused_labels(&mut self) {
+        // the program is entered from outside at the entry point, so that's used even though
+        // nothing references it
+        let entry = Some(self.entry().1).filter(|_| !self.labels.is_empty());
+
+        let unused: Vec<String> = self
+            .sorted_labels()
+            .into_iter()
+            .filter(|(label, addr)| {
+                Some(*addr) != entry
+                    && !self.referenced_labels.contains(*label)
+                    && !self
+                        .entry_patterns
+                        .iter()
+                        .any(|pattern| matches_pattern(pattern, label))
+            })
+            .map(|(label, _)| label.to_string())
+            .collect();
+
+        for label in unused {
+            self.line = self.label_lines[&label];
+            self.add_warning(format!("Label '{label}' is never referenced"));
+        }
+    }
+
+    pub fn add_define(&mut self, constant: String, value: Define) -> color_eyre::Result<()> {
+        // the source declaring a define that was carried over from the first pass
+        if self.defines.get(&constant) == Some(&value) && self.carried_defines.remove(&constant) {
+            return Ok(());
+        }
+
         if self.defines.contains_key(&constant) {
             return Err(eyre!("Definition '{}' has already been declared", constant));
         }
@@ -217,22 +1260,293 @@ impl Program {
         Ok(())
     }
 
+    /// Returns true if a define with this name has been declared
+    pub fn is_define(&self, constant: &str) -> bool {
+        self.defines.contains_key(constant)
+    }
+
+    /// Resolves a define to the number or token it ultimately stands for, following aliases to
+    /// other defines
+    fn resolve(&self, constant: String) -> color_eyre::Result<Define> {
+        // every define visited so far, so that we can detect cycles like A = B, B = A
+        let mut chain = vec![constant];
+
+        loop {
+            let current = chain.last().unwrap();
+            match self.defines.get(current) {
+                Some(Define::Alias(next)) => {
+                    if chain.contains(next) {
+                        chain.push(next.clone());
+                        return Err(eyre!("Definition cycle: {}", chain.join(" -> ")));
+                    }
+                    chain.push(next.clone());
+                }
+                Some(define) => {
+                    debug!("Resolve define: '{}' -> {:?}", chain[0], define);
+                    return Ok(define.clone());
+                }
+                None => return Err(eyre!("Definition '{}' not declared", current)),
+            }
+        }
+    }
+
+    /// Resolves a define to its value, erroring if it's a register alias
     pub fn resolve_define(&self, constant: String) -> color_eyre::Result<u32> {
-        return if let Some(x) = self.defines.get(&constant) {
-            debug!("Resolve define: '{}' -> {}", constant, *x);
-            Ok(*x)
-        } else {
-            Err(eyre!("Definition '{}' not declared", constant))
+        match self.resolve(constant.clone())? {
+            Define::Value(x) => Ok(x),
+            Define::Token(tok) => Err(eyre!(
+                "Error: '{constant}' is a register alias ({}), but a number is required here",
+                tok.as_ref().to_uppercase()
+            )),
+            Define::Alias(_) => unreachable!("resolve() follows aliases"),
         }
     }
 
+    /// Resolves a define to the token it aliases, erroring if it's a number
+    pub fn resolve_token(&self, constant: String) -> color_eyre::Result<ScuDspToken> {
+        match self.resolve(constant.clone())? {
+            Define::Token(tok) => Ok(tok),
+            Define::Value(_) => Err(eyre!(
+                "Error: '{constant}' is a numeric define, but a register is required here"
+            )),
+            Define::Alias(_) => unreachable!("resolve() follows aliases"),
+        }
+    }
+
+    /// Returns true if the define (after following aliases) stands for a token
+    pub fn is_token_alias(&self, constant: &str) -> bool {
+        matches!(self.resolve(constant.into()), Ok(Define::Token(_)))
+    }
+
+    /// Records a warning against the current line
+    pub fn add_warning(&mut self, message: String) {
+        warn!("Line {}: {}", self.line + 1, message);
+        self.warnings.push(Warning {
+            line: self.line,
+            message,
+        });
+    }
+
+    /// Returns the warnings raised so far
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Notes that the loop counter (LOP) was just loaded
+    pub fn mark_lop_loaded(&mut self) {
+        self.lop_loaded = true;
+    }
+
+    /// Returns true if the loop counter was loaded since the last call, consuming the load
+    pub fn take_lop_loaded(&mut self) -> bool {
+        std::mem::take(&mut self.lop_loaded)
+    }
+
+    /// Returns the words committed so far
+    pub fn words(&self) -> &[u32] {
+        &self.prog
+    }
+
+    /// Returns where each word of the program came from, in the same order as [`Program::words`]
+    pub fn word_sources(&self) -> &[WordSource] {
+        &self.sources
+    }
+
+    /// Returns the words arranged for output in the given layout
+    pub fn layout_words(&self, layout: Layout) -> Vec<u32> {
+        match layout {
+            Layout::Flat => self.prog.clone(),
+            Layout::Banked => {
+                let bank_len = self.prog.len().div_ceil(LAYOUT_BANKS);
+                let mut out = vec![self.pad_policy.word(); bank_len * LAYOUT_BANKS];
+                for (i, word) in self.prog.iter().enumerate() {
+                    out[(i % LAYOUT_BANKS) * bank_len + i / LAYOUT_BANKS] = *word;
+                }
+                out
+            }
+        }
+    }
+
+    /// Serialises the program into bytes in the given byte order
+    pub fn to_bytes(&self, endian: Endian) -> Vec<u8> {
+        endian.words_to_bytes(&self.prog)
+    }
+
+    /// Serialises the program into bytes, arranged in the given layout
+    pub fn layout_bytes(&self, layout: Layout, endian: Endian) -> Vec<u8> {
+        endian.words_to_bytes(&self.layout_words(layout))
+    }
+
+    /// Returns the mapping between labels and their addresses
+    pub fn labels(&self) -> &HashMap<String, u32> {
+        &self.labels
+    }
+
+    /// Returns labels ordered by address then name, so output doesn't depend on hash order
+    pub fn sorted_labels(&self) -> Vec<(&str, u32)> {
+        let mut labels: Vec<_> = self.labels.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        labels
+    }
+
+    /// Returns defines ordered by value then name, so output doesn't depend on hash order
+    pub fn sorted_defines(&self) -> Vec<(&str, u32)> {
+        // anything that doesn't resolve is reported by finalise(), so leave it out here
+        let mut defines: Vec<_> = self
+            .defines
+            .keys()
+            .filter_map(|k| Some((k.as_str(), self.resolve_define(k.clone()).ok()?)))
+            .collect();
+        defines.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        defines
+    }
+
+    /// Returns a listing of every label and define, one per line
+    pub fn symbol_table(&self, hex_case: HexCase) -> String {
+        let mut out = String::from("; Labels\n");
+        for (label, addr) in self.sorted_labels() {
+            out += &format!("{label} = ${}\n", hex_case.format(addr, 2));
+        }
+        out += "; Defines\n";
+        for (define, value) in self.sorted_defines() {
+            out += &format!("{define} = ${}\n", hex_case.format(value, 1));
+        }
+        out += &match self.entry() {
+            (Some(label), addr) => format!("; Entry = ${} ({label})\n", hex_case.format(addr, 2)),
+            (None, addr) => format!("; Entry = ${}\n", hex_case.format(addr, 2)),
+        };
+        out
+    }
+
+    /// Checks the program assembled to exactly the expected number of words
+    pub fn check_word_count(&self, expected: u32) -> color_eyre::Result<()> {
+        let actual = self.prog.len();
+        if actual != expected as usize {
+            return Err(eyre!(
+                "Error: Expected the program to be {expected} words, but it assembled to {actual} words"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the program's checksum (see [`Program::checksum`]) is the expected value
+    pub fn check_checksum(&self, expected: u32) -> color_eyre::Result<()> {
+        let actual = self.checksum();
+        if actual != expected {
+            return Err(eyre!(
+                "Error: Checksum mismatch: expected {expected:#010x}, but the program assembled to \
+                {actual:#010x}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bundles committed
+    pub fn bundle_count(&self) -> u32 {
+        // every word is currently a bundle
+        self.prog.len() as u32
+    }
+
+    /// Returns the total number of instructions committed across all bundles
+    pub fn instruction_count(&self) -> u32 {
+        self.instructions
+    }
+
+    /// Counts how many times each distinct word occurs in the program. Words that recur a lot are
+    /// candidates for turning into a loop or subroutine.
+    pub fn bundle_histogram(&self) -> HashMap<u32, u32> {
+        let mut histogram = HashMap::new();
+        for word in &self.prog {
+            *histogram.entry(*word).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Estimates the number of cycles the program takes to run straight through, from top to
+    /// bottom, using [`cycle_cost`] for each bundle. Loops and jumps are not followed.
+    pub fn cycle_estimate(&self) -> u32 {
+        self.prog.iter().map(|word| cycle_cost(*word)).sum()
+    }
+
+    /// Computes the CRC-32 (IEEE) of the program, taking each word in little-endian byte order
+    pub fn checksum(&self) -> u32 {
+        let mut crc = 0xFFFF_FFFF_u32;
+        for byte in self.to_bytes(Endian::Little) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    /// Returns a JSON object summarising the program's size and build statistics
+    pub fn summary_json(&self) -> String {
+        format!(
+            "{{\n  \"words\": {},\n  \"bytes\": {},\n  \"bundles\": {},\n  \"instructions\": {},\n  \
+            \"cycles\": {},\n  \"checksum\": \"{:#010x}\",\n  \"entry\": {},\n  \"warnings\": {}\n}}\n",
+            self.prog.len(),
+            self.prog.len() * 4,
+            self.bundle_count(),
+            self.instruction_count(),
+            self.cycle_estimate(),
+            self.checksum(),
+            self.entry().1,
+            self.warnings.len()
+        )
+    }
+
     pub fn debug_dump(&self) {
         for (i, opcode) in self.prog.iter().enumerate() {
             info!("[{}] {:#034b} {:#010x}", i, opcode, opcode);
         }
     }
 
+    /// Starts a fresh program for another pass over the same source. Only the labels and defines
+    /// found by this pass are carried over, along with the options the program was configured
+    /// with; everything emitted is thrown away. The next pass then knows every label's address up
+    /// front, even those declared after they're used.
+    pub fn clone_for_pass(&self) -> Program {
+        Program {
+            labels: self.labels.clone(),
+            label_sections: self.label_sections.clone(),
+            defines: self.defines.clone(),
+            carried_defines: self.defines.keys().cloned().collect(),
+
+            limits: self.limits,
+            require_org: self.require_org,
+            one_instr_per_line: self.one_instr_per_line,
+            trace_relocations: self.trace_relocations,
+            warn_delay_slot: self.warn_delay_slot,
+            warn_unused_labels: self.warn_unused_labels,
+            entry_patterns: self.entry_patterns.clone(),
+            entry_label: self.entry_label.clone(),
+            word_limit: self.word_limit,
+            pad_policy: self.pad_policy,
+            max_bundle_override: self.max_bundle_override,
+
+            ..Default::default()
+        }
+    }
+
     pub fn set_pc(&mut self, pc: u32) {
         self.pc = pc;
     }
+
+    /// Returns the address the next bundle will be written to
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    /// Returns how many instructions have been added to the bundle currently being built, which
+    /// is 0 between bundles
+    pub fn current_bundle_instructions(&self) -> u32 {
+        self.emitted
+    }
 }
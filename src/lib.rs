@@ -0,0 +1,60 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! SoCUte is an assembler for the Sega Saturn's SCU DSP. The `socute` binary is a thin wrapper
+//! over this library, so tools can assemble programs directly with [`assemble`], drive the
+//! [`tokeniser`], [`parser`] and [`emitter`] themselves for more control, or skip the source text
+//! altogether and build bundles with the [`builder`].
+
+use std::io::Read;
+
+use crate::{
+    emitter::{Endian, Program},
+    parser::document,
+    tokeniser::lex,
+};
+
+pub mod builder;
+pub mod diagnostic;
+pub mod disasm;
+pub mod emitter;
+pub mod output;
+pub mod parser;
+pub mod repl;
+pub mod tokeniser;
+
+/// Assembles a source document, returning the program as little-endian bytes ready to load into
+/// program RAM, the same as `socute asm` writes by default
+pub fn assemble(source: &str, relaxed: bool) -> color_eyre::Result<Vec<u8>> {
+    let mut source = source.to_string();
+    // the last line needs a newline like every other, as in the CLI
+    source += "\n";
+
+    let (prog, result) = assemble_with(&source, Program::default(), relaxed);
+    result?;
+    Ok(prog.to_bytes(Endian::Little))
+}
+
+/// Reads a whole source document, e.g. from stdin, and assembles it like [`assemble`]
+pub fn assemble_reader<R: Read>(mut reader: R, relaxed: bool) -> color_eyre::Result<Vec<u8>> {
+    let mut source = String::new();
+    reader.read_to_string(&mut source)?;
+    assemble(&source, relaxed)
+}
+
+/// Assembles a source document into a program that has already been configured (e.g. with
+/// [`Program::set_require_org`]). The program is returned even on failure, so the caller can see
+/// how far it got.
+pub fn assemble_with(
+    source: &str,
+    mut prog: Program,
+    relaxed: bool,
+) -> (Program, color_eyre::Result<()>) {
+    let mut tokens = lex(source);
+    let result = document(&mut tokens, &mut prog, relaxed);
+    (prog, result)
+}
@@ -4,32 +4,47 @@
 //
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
 // was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand};
 use color_eyre::{
     Section, SectionExt,
+    eyre::eyre,
     owo_colors::{AnsiColors, OwoColorize},
 };
 use env_logger::{Builder, Env};
 use log::warn;
 
-use crate::{emitter::Program, parser::document, tokeniser::lex};
-
-pub mod emitter;
-pub mod parser;
-pub mod tokeniser;
+use socute::{
+    assemble_with, diagnostic,
+    diagnostic::{Diagnostic, Severity},
+    disasm,
+    emitter::{Endian, Layout, PadPolicy, Program},
+    output,
+    output::{Columns, Format, Header, HexCase},
+    parser::{assemble_bundle, document_recovering},
+    repl,
+    tokeniser::lex,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Debug, Subcommand)]
+// only ever constructed once, when parsing the command line, so the size of Asm doesn't matter
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Assemble a single SCU DSP source file
     Asm {
-        /// Source file
+        /// Source file, or an http(s) URL to fetch it from (needs the `net` feature)
         src: PathBuf,
 
-        /// Destination file
+        /// Destination file, or `-` for stdout. Defaults to the source file with its extension
+        /// replaced by the format's, e.g. `prog.asm` -> `prog.bin`, or stdout if reading stdin.
         dest: Option<PathBuf>,
 
         #[arg(long, action)]
@@ -40,6 +55,201 @@ enum Commands {
         #[arg(long, action)]
         /// Print internal parser debug information
         debug: bool,
+
+        #[arg(long, action)]
+        /// Don't append a trailing newline to the source before assembling it
+        no_implicit_newline: bool,
+
+        #[arg(long)]
+        /// Write a JSON summary of the build statistics to this file
+        summary: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Fail unless the program assembles to exactly this many words
+        expect_words: Option<u32>,
+
+        #[arg(long, value_parser = output::parse_hex_u32)]
+        /// Fail unless the CRC-32 of the program (as in the --summary) is this hex value
+        expect_checksum: Option<u32>,
+
+        #[arg(long)]
+        /// Write the labels and defines to this file
+        symbols: Option<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t)]
+        /// How to arrange words in the output file
+        layout: Layout,
+
+        #[arg(long, value_enum, default_value_t)]
+        /// Format of the output file
+        format: Format,
+
+        #[arg(long, value_enum, default_value_t)]
+        /// Header to write before the program in binary output
+        header: Header,
+
+        #[arg(long, value_parser = output::parse_u32, default_value_t = output::HEADER_MAGIC)]
+        /// Magic number written at the start of the header
+        header_magic: u32,
+
+        #[arg(long, value_parser = output::parse_u32, default_value_t = output::HEADER_VERSION)]
+        /// Version written in the header
+        header_version: u32,
+
+        #[arg(long)]
+        /// Instead of one output file, write the program in banks of this many words to
+        /// `<dest>.bank0.bin`, `<dest>.bank1.bin`, etc. along with a `<dest>.manifest` listing their
+        /// load addresses
+        split_banks: Option<usize>,
+
+        #[arg(long, value_parser = PadPolicy::parse)]
+        /// What to fill padding with: `nop`, `trap` (ENDI, which halts the DSP and raises its end
+        /// interrupt) or `value:N`. Also pads the last bank written by --split-banks to full size.
+        pad_policy: Option<PadPolicy>,
+
+        #[arg(long)]
+        /// Name of the array declared by the c-array and rust-array formats, or the module name in
+        /// the header of srec output. Defaults to the name of the output file
+        symbol: Option<String>,
+
+        #[arg(long, value_enum, default_value_t)]
+        /// Case of hex digits in textual output (C arrays, S-records, Intel HEX, Verilog, symbols)
+        hex_case: HexCase,
+
+        #[arg(long)]
+        /// Write a listing annotated with estimated cycle counts to this file
+        profile: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Write a report of bundles that occur more than once to this file
+        bundle_histogram: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Write a disassembly of the assembled program to this file, to check the encoding
+        disasm_listing: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Write a listing of the address, word and source line of each bundle to this file
+        listing: Option<PathBuf>,
+
+        #[arg(long, action)]
+        /// Print each word of the program with its source line, labels and decoded instructions
+        dump_ir: bool,
+
+        #[arg(long, default_value_t = 4)]
+        /// Width of a tab stop when lining up the instructions of a bundle in listings
+        tab_width: usize,
+
+        #[arg(long, action)]
+        /// Separate the instructions of a bundle in listings with tabs rather than spaces
+        use_tabs: bool,
+
+        #[arg(long, action)]
+        /// Error if any instruction comes before an ORG directive
+        require_org: bool,
+
+        #[arg(long, action)]
+        /// Error if a line holds more than one instruction (other than flow control); bundles are
+        /// then built up with line continuations
+        one_instr_per_line: bool,
+
+        #[arg(long, action)]
+        /// Log each forward reference as it's recorded and patched
+        trace_relocations: bool,
+
+        #[arg(long, action)]
+        /// Warn when the bundle after a JMP (its delay slot, which runs before the jump takes
+        /// effect) isn't a NOP
+        warn_delay_slot: bool,
+
+        #[arg(long, action)]
+        /// Warn about labels that are never referenced
+        warn_unused_labels: bool,
+
+        #[arg(long)]
+        /// Label execution starts at (by default, the first label)
+        entry: Option<String>,
+
+        #[arg(long)]
+        /// Most instructions allowed in a bundle (1-6). Overrides `.maxbundle` in the source.
+        max_bundle: Option<u32>,
+
+        #[arg(long)]
+        /// Abort if the program grows past this many words. Useful as a safety net when assembling
+        /// untrusted input.
+        limit_words: Option<u32>,
+
+        #[arg(long, action)]
+        /// Print errors and warnings to stderr as JSON, and exit with an error if there are any
+        /// errors. Errors found after assembly (e.g. from --expect-words) are reported on line 1.
+        json_diagnostics: bool,
+
+        #[arg(long)]
+        /// Labels matching this pattern (`*` matches anything) are entry points, and aren't warned
+        /// about by --warn-unused-labels. May be given more than once.
+        entry_pattern: Vec<String>,
+    },
+
+    /// Assemble two source files and show where their output differs
+    Diff {
+        /// First source file
+        a: PathBuf,
+
+        /// Second source file
+        b: PathBuf,
+
+        #[arg(long, action)]
+        /// Relaxes some parsing rules to compile files written for the original assembler on a
+        /// best-effort basis
+        relaxed: bool,
+    },
+
+    /// Assemble every job listed in a file, one `src -> dest` per line, continuing past failures
+    Batch {
+        /// File listing the jobs. Relative paths in it are relative to the file itself.
+        jobfile: PathBuf,
+
+        #[arg(long, action)]
+        /// Relaxes some parsing rules to compile files written for the original assembler on a
+        /// best-effort basis
+        relaxed: bool,
+    },
+
+    /// Check an assembled binary for words that don't decode to a legal bundle
+    Lint {
+        /// Binary to check, as written by `asm`
+        bin: PathBuf,
+
+        #[arg(long, value_parser = output::parse_u32, default_value_t = output::HEADER_MAGIC)]
+        /// Magic number of a header to skip, if the binary starts with one
+        header_magic: u32,
+    },
+
+    /// Disassemble a binary back into source, one bundle per line
+    Disasm {
+        /// Binary to disassemble, as written by `asm`, or `-` for stdin
+        src: PathBuf,
+
+        /// Destination file, or `-` for stdout. Defaults to stdout.
+        dest: Option<PathBuf>,
+
+        #[arg(long, value_parser = output::parse_u32, default_value_t = output::HEADER_MAGIC)]
+        /// Magic number of a header to skip, if the binary starts with one
+        header_magic: u32,
+    },
+
+    /// Print the encoding of a single bundle, e.g. "MOV M3, P"
+    Encode {
+        /// Instructions to encode
+        instruction: String,
+    },
+
+    /// Interactively assemble lines read from stdin
+    Repl {
+        #[arg(long, action)]
+        /// Relaxes some parsing rules to compile files written for the original assembler on a
+        /// best-effort basis
+        relaxed: bool,
     },
 
     /// Prints version information.
@@ -57,9 +267,253 @@ struct SoCuteCli {
     command: Commands,
 }
 
+/// Returns true if the source is an http(s) URL rather than a file
+fn is_url(src: &Path) -> bool {
+    let src = src.to_string_lossy();
+    src.starts_with("http://") || src.starts_with("https://")
+}
+
+/// Downloads a source file, giving up if the server takes too long
+#[cfg(feature = "net")]
+fn fetch_url(url: &str) -> color_eyre::Result<String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(std::time::Duration::from_secs(30)))
+        .build()
+        .into();
+
+    agent
+        .get(url)
+        .call()
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .map_err(|error| eyre!("Error: Failed to fetch '{url}': {error}"))
+}
+
+#[cfg(not(feature = "net"))]
+fn fetch_url(url: &str) -> color_eyre::Result<String> {
+    Err(eyre!(
+        "Error: Can't fetch '{url}', as SoCUte was built without the `net` feature"
+    ))
+}
+
+/// Reads a source file
+fn read_source(src: &PathBuf, implicit_newline: bool) -> color_eyre::Result<String> {
+    let mut string = if is_stdio(src) {
+        let mut string = String::new();
+        io::stdin().read_to_string(&mut string)?;
+        string
+    } else if is_url(src) {
+        fetch_url(&src.to_string_lossy())?
+    } else {
+        let mut f = File::open(src)?;
+        let mut string = String::new();
+        f.read_to_string(&mut string)?;
+        string
+    };
+    if implicit_newline {
+        // add extra newline in case file doesn't have its own
+        string += "\n";
+    }
+
+    Ok(string)
+}
+
+/// Reads and assembles a source file into `prog`, attaching the offending line to any error
+fn assemble_file(
+    src: &PathBuf,
+    prog: Program,
+    relaxed: bool,
+    implicit_newline: bool,
+) -> color_eyre::Result<Program> {
+    let string = read_source(src, implicit_newline)?;
+    assemble_source(&string, prog, relaxed)
+}
+
+/// Assembles source that has already been read into `prog`, attaching the offending line to any
+/// error. If there's more than one error in the source, they're all reported together.
+fn assemble_source(string: &str, prog: Program, relaxed: bool) -> color_eyre::Result<Program> {
+    let lines: Vec<&str> = string.lines().collect();
+    let line = |index: u32| {
+        lines
+            .get(index as usize)
+            .copied()
+            .unwrap_or("error fetching context")
+    };
+
+    // assembling stops at the first error, so keep a copy to go back over the rest of the source
+    let mut fresh = prog.clone();
+    let (prog, result) = assemble_with(string, prog, relaxed);
+
+    let Err(error) = result else {
+        return Ok(prog);
+    };
+
+    let suggest_relaxed = !relaxed && relaxed_gets_further(string, fresh.clone(), prog.line);
+    let errors = document_recovering(&mut lex(string), &mut fresh, relaxed);
+
+    let report = if errors.len() > 1 {
+        let mut report = eyre!("Error: Found {} errors", errors.len());
+        for error in errors {
+            let context = error_context(error.line, line(error.line), error.column);
+            let header = error.error.to_string().color(AnsiColors::Red).to_string();
+            report = report.section(context.header(header));
+        }
+        report
+    } else {
+        let context = error_context(prog.line, line(prog.line), prog.column.clone());
+        error.with_section(move || context.header("Assembly context:".color(AnsiColors::Green)))
+    };
+
+    if suggest_relaxed {
+        return Err(report.suggestion(
+            "This looks like a legacy document; try again in relaxed mode (--relaxed)",
+        ));
+    }
+    Err(report)
+}
+
+/// Returns true if relaxed mode gets past the line (starting at 0) that strict mode failed on,
+/// meaning the source is probably written in the legacy syntax relaxed mode accepts, rather than
+/// being plain wrong
+fn relaxed_gets_further(string: &str, prog: Program, failed_line: u32) -> bool {
+    let (prog, result) = assemble_with(string, prog, true);
+    result.is_ok() || prog.line > failed_line
+}
+
+/// Formats the offending line (starting at 0) for an error. If the error points at a token, it's
+/// underlined with carets underneath, like rustc does.
+fn error_context(index: u32, line: &str, columns: Option<Range<u32>>) -> String {
+    let number = (index + 1).to_string();
+    let mut context = format!("{number} |    {}", line.trim());
+
+    if let Some(columns) = columns {
+        let indent = line.chars().count() - line.trim_start().chars().count();
+        // keep any tabs so the carets still line up
+        let padding: String = line
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .skip(indent)
+            .take((columns.start as usize).saturating_sub(indent))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(columns.len().max(1));
+        context += &format!("\n{} |    {padding}{carets}", " ".repeat(number.len()));
+    }
+
+    context
+}
+
+/// Parses a batch job file into (source, destination) pairs. Each line is `src -> dest`; blank lines
+/// and `;` comments are skipped, and relative paths are resolved against `base`.
+fn parse_jobs(text: &str, base: &Path) -> color_eyre::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut jobs = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some((src, dest)) = line.split_once("->") else {
+            return Err(eyre!(
+                "Syntax error: Job on line {} should be written 'src -> dest'",
+                index + 1
+            ));
+        };
+        jobs.push((base.join(src.trim()), base.join(dest.trim())));
+    }
+
+    Ok(jobs)
+}
+
+/// Assembles each job to a flat binary, reporting how each went and then a summary to `out`. A
+/// failed job doesn't stop the rest. Returns the number of jobs that failed.
+fn run_jobs<W: Write>(
+    jobs: &[(PathBuf, PathBuf)],
+    relaxed: bool,
+    out: &mut W,
+) -> color_eyre::Result<usize> {
+    let mut failed = 0;
+
+    for (src, dest) in jobs {
+        let result = assemble_file(src, Program::default(), relaxed, true)
+            .and_then(|prog| Ok(fs::write(dest, prog.to_bytes(Endian::Little))?));
+
+        match result {
+            Ok(()) => writeln!(out, "ok    {} -> {}", src.display(), dest.display())?,
+            Err(error) => {
+                failed += 1;
+                writeln!(out, "FAIL  {}: {error}", src.display())?;
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "{} job(s): {} succeeded, {failed} failed",
+        jobs.len(),
+        jobs.len() - failed
+    )?;
+
+    Ok(failed)
+}
+
+/// Returns where to write the output when no destination was given: next to the source, with the
+/// output format's extension. Sources fetched from a URL are written to the current directory.
+fn default_dest(src: &Path, format: Format) -> color_eyre::Result<PathBuf> {
+    if is_stdio(src) {
+        return Ok(PathBuf::from(STDIO));
+    }
+
+    let dest = if is_url(src) {
+        PathBuf::from(src.file_name().unwrap_or_default()).with_extension(format.extension())
+    } else {
+        src.with_extension(format.extension())
+    };
+    if dest == src {
+        return Err(eyre!(
+            "Error: Writing output next to '{}' would overwrite it; give a destination file",
+            src.display()
+        ));
+    }
+
+    Ok(dest)
+}
+
+/// Path standing for stdin as a source, or stdout as a destination
+const STDIO: &str = "-";
+
+/// Returns true if the path is `-`, for stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO)
+}
+
+/// Writes the output file, or to stdout if the destination is `-`
+fn write_output(dest: &Path, contents: &[u8]) -> color_eyre::Result<()> {
+    if is_stdio(dest) {
+        let mut stdout = io::stdout();
+        stdout.write_all(contents)?;
+        stdout.flush()?;
+    } else {
+        fs::write(dest, contents)?;
+    }
+
+    Ok(())
+}
+
+/// Encodes a single bundle, formatting the word in hex and binary
+fn encode(instruction: &str) -> color_eyre::Result<String> {
+    let word = assemble_bundle(instruction)?;
+    Ok(format!("{word:#010x}\n{word:#034b}"))
+}
+
 fn main() -> color_eyre::Result<()> {
     let args = SoCuteCli::parse();
-    let env = Env::new().filter_or("RUST_LOG", "debug");
+    // parser debug output is only useful when explicitly asked for
+    let level = match args.command {
+        Commands::Asm { debug: true, .. } => "debug",
+        _ => "info",
+    };
+    let env = Env::new().filter_or("RUST_LOG", level);
     Builder::from_env(env).init();
     color_eyre::install()?;
 
@@ -68,39 +522,260 @@ fn main() -> color_eyre::Result<()> {
             src,
             dest,
             relaxed,
-            debug,
+            debug: _,
+            no_implicit_newline,
+            summary,
+            expect_words,
+            expect_checksum,
+            symbols,
+            layout,
+            format,
+            header,
+            header_magic,
+            header_version,
+            split_banks,
+            pad_policy,
+            symbol,
+            hex_case,
+            profile,
+            bundle_histogram,
+            disasm_listing,
+            listing,
+            dump_ir,
+            tab_width,
+            use_tabs,
+            require_org,
+            one_instr_per_line,
+            trace_relocations,
+            warn_delay_slot,
+            warn_unused_labels,
+            entry_pattern,
+            entry,
+            max_bundle,
+            limit_words,
+            json_diagnostics,
         } => {
             if relaxed {
                 warn!("Running in relaxed mode; use only to parse legacy documents.");
             }
 
-            let mut f = File::open(src)?;
-            let mut string = String::new();
-            f.read_to_string(&mut string)?;
-            // add extra newline in case file doesn't have its own
-            string += "\n";
+            let mut prog = Program::default();
+            prog.set_require_org(require_org);
+            prog.set_one_instr_per_line(one_instr_per_line);
+            prog.set_trace_relocations(trace_relocations);
+            prog.set_warn_delay_slot(warn_delay_slot);
+            prog.set_warn_unused_labels(warn_unused_labels, entry_pattern);
+            prog.set_entry(entry);
+            prog.set_word_limit(limit_words);
+            prog.set_pad_policy(pad_policy.unwrap_or_default());
+            prog.set_max_bundle_override(max_bundle)?;
 
-            let lines: Vec<String> = string.lines().map(|x| x.into()).collect();
+            let source = read_source(&src, !no_implicit_newline)?;
+            let (prog, mut diagnostics) = if json_diagnostics {
+                // the diagnose pass assembles the whole program when there are no errors, so
+                // there's no need to go over the source again
+                let (prog, diagnostics) = diagnostic::diagnose_program(&source, prog, relaxed);
+                if diagnostics
+                    .iter()
+                    .any(|diagnostic| diagnostic.severity == Severity::Error)
+                {
+                    eprint!(
+                        "{}",
+                        diagnostic::diagnostics_json(&src.to_string_lossy(), &diagnostics)
+                    );
+                    std::process::exit(1);
+                }
+                (prog, diagnostics)
+            } else {
+                (assemble_source(&source, prog, relaxed)?, Vec::new())
+            };
 
-            let mut tokens = lex(string.as_str());
-            let mut prog = Program::default();
-            let result = document(&mut tokens, &mut prog, relaxed);
-
-            match result {
-                Ok(_) => {}
-                Err(error) => {
-                    let index = prog.line;
-                    let line = match lines.get::<usize>(index as usize) {
-                        Some(l) => l,
-                        None => "error fetching context",
+            // anything that goes wrong from here on is reported as a diagnostic too, when asked
+            let finish = || -> color_eyre::Result<()> {
+                if let Some(expected) = expect_words {
+                    prog.check_word_count(expected)?;
+                }
+                if let Some(expected) = expect_checksum {
+                    prog.check_checksum(expected)?;
+                }
+
+                let dest = match dest {
+                    Some(dest) => dest,
+                    None => default_dest(&src, format)?,
+                };
+
+                if header != Header::None && (format != Format::Bin || split_banks.is_some()) {
+                    return Err(eyre!(
+                        "Error: --header can only be used with --format bin, without --split-banks"
+                    ));
+                }
+
+                if let Some(bank_words) = split_banks {
+                    if is_stdio(&dest) {
+                        return Err(eyre!(
+                            "Error: --split-banks writes several files, so it needs a destination file"
+                        ));
+                    }
+                    let banks = output::split_banks(
+                        prog.words(),
+                        bank_words,
+                        pad_policy.map(PadPolicy::word),
+                    )?;
+                    for (index, bank) in banks.iter().enumerate() {
+                        fs::write(
+                            output::bank_path(&dest, index),
+                            Endian::Little.words_to_bytes(bank),
+                        )?;
+                    }
+                    fs::write(
+                        dest.with_extension("manifest"),
+                        output::bank_manifest(&dest, &banks, bank_words),
+                    )?;
+                } else {
+                    let words = prog.layout_words(layout);
+                    let symbol = symbol.unwrap_or_else(|| format.default_symbol(&dest));
+                    let symbol = symbol.as_str();
+                    let contents = match format {
+                        Format::Bin => {
+                            let mut bytes = Vec::new();
+                            if header == Header::Simple {
+                                let (_, entry) = prog.entry();
+                                let header = output::simple_header(
+                                    header_magic,
+                                    header_version,
+                                    words.len(),
+                                    entry,
+                                );
+                                bytes = Endian::Little.words_to_bytes(&header);
+                            }
+                            bytes.extend(prog.layout_bytes(layout, Endian::Little));
+                            bytes
+                        }
+                        Format::CArray => output::c_array(&words, symbol, hex_case).into(),
+                        Format::RustArray => output::rust_array(&words, symbol, hex_case).into(),
+                        Format::Srec => {
+                            let bytes = Endian::Little.words_to_bytes(&words);
+                            let (_, entry) = prog.entry();
+                            output::srec(&bytes, symbol, entry * 4, hex_case).into()
+                        }
+                        Format::Verilog => output::readmemh(&words, hex_case).into(),
+                        Format::Ihex => {
+                            let bytes = Endian::Little.words_to_bytes(&words);
+                            output::ihex(&bytes, hex_case).into()
+                        }
                     };
-                    // TODO if we're not in --relaxed mode, suggest running --relaxed
-                    return Err(error.with_section(move || {
-                        format!("{} |    {}", index + 1, line.trim())
-                            .header("Assembly context:".color(AnsiColors::Green))
-                    }));
+                    write_output(&dest, &contents)?;
+                }
+
+                if let Some(summary) = summary {
+                    fs::write(summary, prog.summary_json())?;
+                }
+
+                if let Some(symbols) = symbols {
+                    fs::write(symbols, prog.symbol_table(hex_case))?;
+                }
+
+                let columns = Columns {
+                    tab_width,
+                    use_tabs,
+                };
+                if let Some(profile) = profile {
+                    fs::write(profile, disasm::profile(prog.words(), &columns))?;
+                }
+
+                if let Some(bundle_histogram) = bundle_histogram {
+                    fs::write(
+                        bundle_histogram,
+                        disasm::histogram_report(&prog.bundle_histogram()),
+                    )?;
+                }
+
+                if let Some(disasm_listing) = disasm_listing {
+                    fs::write(disasm_listing, disasm::listing(prog.words(), &columns))?;
+                }
+
+                if let Some(listing) = listing {
+                    fs::write(
+                        listing,
+                        output::source_listing(prog.words(), prog.word_sources(), &source),
+                    )?;
+                }
+
+                if dump_ir {
+                    print!("{}", disasm::ir_dump(&prog));
+                }
+                Ok(())
+            };
+            let result = finish();
+
+            if json_diagnostics {
+                if let Err(error) = &result {
+                    diagnostics.insert(0, Diagnostic::error(error.to_string()));
+                }
+                eprint!(
+                    "{}",
+                    diagnostic::diagnostics_json(&src.to_string_lossy(), &diagnostics)
+                );
+                if result.is_err() {
+                    std::process::exit(1);
                 }
             }
+            result?;
+        }
+        Commands::Diff { a, b, relaxed } => {
+            let prog_a = assemble_file(&a, Program::default(), relaxed, true)?;
+            let prog_b = assemble_file(&b, Program::default(), relaxed, true)?;
+
+            let diffs = disasm::diff(prog_a.words(), prog_b.words());
+            for diff in &diffs {
+                println!("{diff}");
+            }
+            if !diffs.is_empty() {
+                return Err(eyre!("Programs differ at {} address(es)", diffs.len()));
+            }
+        }
+        Commands::Batch { jobfile, relaxed } => {
+            let base = jobfile.parent().unwrap_or(Path::new("."));
+            let jobs = parse_jobs(&fs::read_to_string(&jobfile)?, base)?;
+
+            let failed = run_jobs(&jobs, relaxed, &mut io::stdout())?;
+            if failed > 0 {
+                return Err(eyre!("{failed} of {} job(s) failed", jobs.len()));
+            }
+        }
+        Commands::Lint { bin, header_magic } => {
+            let words = Endian::Little.bytes_to_words(&fs::read(&bin)?)?;
+
+            let problems = disasm::lint(output::strip_header(&words, header_magic));
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                return Err(eyre!("{} word(s) have illegal encodings", problems.len()));
+            }
+        }
+        Commands::Disasm {
+            src,
+            dest,
+            header_magic,
+        } => {
+            let bytes = if is_stdio(&src) {
+                let mut bytes = Vec::new();
+                io::stdin().read_to_end(&mut bytes)?;
+                bytes
+            } else {
+                fs::read(&src)?
+            };
+            let words = Endian::Little.bytes_to_words(&bytes)?;
+
+            let source = disasm::source(output::strip_header(&words, header_magic));
+            write_output(&dest.unwrap_or_else(|| STDIO.into()), source.as_bytes())?;
+        }
+        Commands::Encode { instruction } => {
+            println!("{}", encode(&instruction)?);
+        }
+        Commands::Repl { relaxed } => {
+            repl::repl(io::stdin().lock(), &mut io::stdout(), relaxed)?;
         }
         Commands::Version {} => {
             println!(
@@ -112,3 +787,487 @@ fn main() -> color_eyre::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str, relaxed: bool) -> (Program, color_eyre::Result<()>) {
+        assemble_with(source, Program::default(), relaxed)
+    }
+
+    #[test]
+    fn test_no_trailing_newline() {
+        let (prog, result) = assemble("CLR A\nMOV MUL, P\nENDI", false);
+        assert!(result.is_ok());
+        assert_eq!(prog.words(), &[0x0002_0000, 0x0100_0000, 0xF800_0000]);
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let (prog, result) = assemble("CLR A  MOV MUL, P\nMVI #3, LOP\nLPS\nLPS\n", false);
+        assert!(result.is_ok());
+        assert_eq!(
+            prog.summary_json(),
+            r#"{
+  "words": 4,
+  "bytes": 16,
+  "bundles": 4,
+  "instructions": 5,
+  "cycles": 6,
+  "checksum": "0x4ed4422d",
+  "entry": 0,
+  "warnings": 1
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_expect_words() {
+        let (prog, result) = assemble("CLR A\nMOV MUL, P\n", false);
+        assert!(result.is_ok());
+        assert!(prog.check_word_count(2).is_ok());
+
+        let error = prog.check_word_count(3).unwrap_err().to_string();
+        assert!(error.contains("Expected the program to be 3 words"));
+        assert!(error.contains("assembled to 2 words"));
+    }
+
+    #[test]
+    fn test_source_listing() {
+        let source = "start: CLR A\n    AD2  MOV MUL,P   ; accumulate\nEND\n";
+        let (prog, result) = assemble(source, false);
+        assert!(result.is_ok());
+
+        assert_eq!(
+            output::source_listing(prog.words(), prog.word_sources(), source),
+            "; addr  word      line  source\n\
+             $00    00020000     1  start: CLR A\n\
+             $01    19000000     2      AD2  MOV MUL,P   ; accumulate\n\
+             $02    F0000000     3  END\n"
+        );
+    }
+
+    #[test]
+    fn test_expect_checksum() {
+        let (prog, result) = assemble("CLR A\nMOV MUL, P\n", false);
+        assert!(result.is_ok());
+        assert!(prog.check_checksum(prog.checksum()).is_ok());
+
+        let error = prog.check_checksum(0x1234_5678).unwrap_err().to_string();
+        assert!(error.contains("expected 0x12345678"));
+        assert!(error.contains(&format!("assembled to {:#010x}", prog.checksum())));
+    }
+
+    #[test]
+    fn test_symbols_deterministic() {
+        let source = "ZETA = 3\nALPHA = 3\nstart:\nbravo:\nCLR A\nalpha:\nCLR A\nzulu:\n";
+        let (first, result) = assemble(source, false);
+        assert!(result.is_ok());
+        let (second, _) = assemble(source, false);
+
+        assert_eq!(
+            first.symbol_table(HexCase::Upper),
+            second.symbol_table(HexCase::Upper)
+        );
+        assert_eq!(
+            first.symbol_table(HexCase::Upper),
+            "; Labels\nbravo = $00\nstart = $00\nalpha = $01\nzulu = $02\n; Defines\nALPHA = $3\nZETA = $3\n; Entry = $00 (bravo)\n"
+        );
+    }
+
+    #[test]
+    fn test_symbols_after_relocation() {
+        // data labels are reported where they end up, after the code, not where they were declared
+        let (prog, result) = assemble(
+            ".data\ntable:\nDW 1\n.code\nstart:\nCLR A\nJMP table\n",
+            false,
+        );
+        assert!(result.is_ok());
+
+        assert_eq!(
+            prog.symbol_table(HexCase::Upper),
+            "; Labels\nstart = $00\ntable = $02\n; Defines\n; Entry = $00 (start)\n"
+        );
+        assert!(prog.summary_json().contains("\"entry\": 0,"));
+    }
+
+    #[test]
+    fn test_layouts() {
+        let (prog, result) = assemble("AND\nOR\nXOR\nADD\nSUB\nAD2\nSR\nRR\n", false);
+        assert!(result.is_ok());
+
+        let flat = prog.layout_words(Layout::Flat);
+        assert_eq!(flat, prog.words());
+
+        let banked = prog.layout_words(Layout::Banked);
+        let order = [0, 4, 1, 5, 2, 6, 3, 7];
+        assert_eq!(banked, order.map(|i| flat[i]));
+    }
+
+    #[test]
+    fn test_pad_policy() {
+        // 5 words make banks of 2, so the last 3 banks each end in a padding word
+        let (mut prog, result) = assemble("AND\nOR\nXOR\nADD\nSUB\n", false);
+        assert!(result.is_ok());
+
+        let policies = [
+            ("nop", 0),
+            ("trap", 0xF800_0000),
+            ("value:0x12345678", 0x1234_5678),
+            ("VALUE:42", 42),
+        ];
+        for (arg, fill) in policies {
+            prog.set_pad_policy(PadPolicy::parse(arg).unwrap());
+            let banked = prog.layout_words(Layout::Banked);
+            assert_eq!(banked.len(), 8);
+            for padding in [3, 5, 7] {
+                assert_eq!(banked[padding], fill, "{arg}");
+            }
+        }
+
+        assert!(PadPolicy::parse("value:").is_err());
+        assert!(PadPolicy::parse("halt").is_err());
+    }
+
+    #[test]
+    fn test_require_org() {
+        let mut prog = Program::default();
+        prog.set_require_org(true);
+
+        let (_, result) = assemble_with("FOO = 1\nCLR A\nORG 0\n", prog.clone(), false);
+        assert!(result.unwrap_err().to_string().contains("before ORG"));
+
+        let (_, result) = assemble_with("FOO = 1\nORG 0\nCLR A\n", prog, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_one_instr_per_line() {
+        let source = "CLR A  MOV MUL,P\n";
+        let (_, result) = assemble_with(source, Program::default(), false);
+        assert!(result.is_ok());
+
+        let mut prog = Program::default();
+        prog.set_one_instr_per_line(true);
+        let (_, result) = assemble_with(source, prog.clone(), false);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("More than one instruction on a line")
+        );
+
+        // the same bundle split over lines is fine, as is a lone flow control instruction
+        let (_, result) = assemble_with("CLR A \\\nMOV MUL,P\nEND\n", prog, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_encode() -> color_eyre::Result<()> {
+        assert_eq!(
+            encode("CLR A")?,
+            "0x00020000\n0b00000000000000100000000000000000"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_warn_delay_slot() {
+        let mut prog = Program::default();
+        prog.set_warn_delay_slot(true);
+
+        let source = "JMP one\nNOP\none:\nJMP two\nCLR A\ntwo:\nJMP three\nEND\nthree:\nCLR A\n";
+        let (prog, result) = assemble_with(source, prog, false);
+        assert!(result.is_ok());
+
+        // NOP slots are fine, and the bundle after the slot isn't checked
+        let warnings: Vec<_> = prog
+            .warnings()
+            .iter()
+            .map(|w| (w.line, &w.message))
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].0, 4);
+        assert!(warnings[0].1.contains("runs before the jump"));
+        assert_eq!(warnings[1].0, 7);
+        assert!(
+            warnings[1]
+                .1
+                .contains("Flow control instruction in the delay slot")
+        );
+    }
+
+    #[test]
+    fn test_warn_unused_labels() {
+        let mut prog = Program::default();
+        prog.set_warn_unused_labels(true, vec!["entry_*".into()]);
+
+        let source = "start:\nCLR A\nloop:\nJMP loop\nunused:\nAD2\nentry_irq:\nENDI\n";
+        let (prog, result) = assemble_with(source, prog, false);
+        assert!(result.is_ok());
+
+        let warnings: Vec<_> = prog.warnings().iter().map(|w| &w.message).collect();
+        assert_eq!(warnings, ["Label 'unused' is never referenced"]);
+        assert_eq!(prog.warnings()[0].line, 4);
+    }
+
+    #[test]
+    fn test_trace_relocations() {
+        let mut prog = Program::default();
+        prog.set_trace_relocations(true);
+
+        let (prog, result) = assemble_with("CLR A\nJMP later\nCLR A\nlater:\nENDI\n", prog, false);
+        assert!(result.is_ok());
+        assert_eq!(
+            prog.relocation_trace(),
+            &[
+                "Relocation: word $01 references 'later' (line 2)",
+                "Patched: 'later' -> $03, word $01 = 0xd0000003",
+            ]
+        );
+
+        // quiet unless asked for
+        let (prog, _) = assemble("JMP later\nlater:\n", false);
+        assert!(prog.relocation_trace().is_empty());
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let (prog, result) = assemble("MOV MUL, P\nENDI\n", false);
+        assert!(result.is_ok());
+
+        assert_eq!(
+            prog.to_bytes(Endian::Big),
+            [0x01, 0x00, 0x00, 0x00, 0xF8, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            prog.to_bytes(Endian::Little),
+            [0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xF8]
+        );
+
+        for endian in [Endian::Big, Endian::Little] {
+            assert_eq!(
+                endian.bytes_to_words(&prog.to_bytes(endian)).unwrap(),
+                prog.words()
+            );
+        }
+        assert!(Endian::Little.bytes_to_words(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_directives_only() {
+        let (prog, result) = assemble(
+            "ONE EQU 1\nTWO EQU 2\nTHREE EQU 3\nORG 0\nIF TWO > ONE\nENDIF\n",
+            false,
+        );
+        assert!(result.is_ok());
+        assert!(prog.words().is_empty());
+        assert_eq!(prog.bundle_count(), 0);
+        assert_eq!(
+            prog.symbol_table(HexCase::Upper),
+            "; Labels\n; Defines\nONE = $1\nTWO = $2\nTHREE = $3\n; Entry = $00\n"
+        );
+    }
+
+    #[test]
+    fn test_entry() {
+        let source = "start:\nCLR A\nmain:\nAD2\n";
+
+        let mut prog = Program::default();
+        prog.set_entry(Some("main".into()));
+        let (prog, result) = assemble_with(source, prog, false);
+        assert!(result.is_ok());
+        assert_eq!(prog.entry(), (Some("main"), 1));
+        assert!(prog.summary_json().contains("\"entry\": 1,"));
+        assert!(
+            prog.symbol_table(HexCase::Upper)
+                .ends_with("; Entry = $01 (main)\n")
+        );
+
+        // defaults to the first label
+        let (prog, _) = assemble(source, false);
+        assert_eq!(prog.entry(), (Some("start"), 0));
+
+        let mut prog = Program::default();
+        prog.set_entry(Some("missing".into()));
+        let (_, result) = assemble_with(source, prog, false);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("not a declared label")
+        );
+    }
+
+    #[test]
+    fn test_batch() -> color_eyre::Result<()> {
+        let dir = std::env::temp_dir().join(format!("socute-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("good.asm"), "CLR A\nENDI\n")?;
+        fs::write(dir.join("bad.asm"), "MOV , X\n")?;
+
+        let jobs = parse_jobs(
+            "; two jobs\ngood.asm -> good.bin\n\nbad.asm -> bad.bin\n",
+            &dir,
+        )?;
+        assert_eq!(jobs.len(), 2);
+
+        let mut out = Vec::new();
+        let failed = run_jobs(&jobs, false, &mut out)?;
+        let out = String::from_utf8(out)?;
+
+        assert_eq!(failed, 1);
+        assert_eq!(
+            fs::read(dir.join("good.bin"))?,
+            [0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0xF8]
+        );
+        assert!(!dir.join("bad.bin").exists());
+        assert!(out.contains("bad.asm: Syntax error: Expected a source operand"));
+        assert!(out.ends_with("2 job(s): 1 succeeded, 1 failed\n"));
+
+        assert!(parse_jobs("good.asm good.bin\n", &dir).is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_words() {
+        let mut prog = Program::default();
+        prog.set_word_limit(Some(2));
+        let (_, result) = assemble_with("CLR A\nCLR A\n", prog.clone(), false);
+        assert!(result.is_ok());
+
+        let (_, result) = assemble_with("CLR A\nCLR A\nCLR A\n", prog.clone(), false);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Program exceeds the limit of 2 words set by --limit-words")
+        );
+
+        // data counts too
+        let (_, result) = assemble_with("CLR A\n.data\nDW 1, 2\n", prog, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_error_context() {
+        assert_eq!(error_context(1, "  MOV FOO, X", None), "2 |    MOV FOO, X");
+        assert_eq!(
+            error_context(1, "  MOV FOO, X", Some(6..9)),
+            "2 |    MOV FOO, X\n  |        ^^^"
+        );
+        assert_eq!(
+            error_context(9, "\tMOV\tFOO, X", Some(5..8)),
+            "10 |    MOV\tFOO, X\n   |       \t^^^"
+        );
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let error =
+            assemble_source("MOV ???\nCLR A\nAD2 OR\n", Program::default(), false).unwrap_err();
+        assert_eq!(error.to_string(), "Error: Found 2 errors");
+
+        // a single error is reported as it is
+        let error = assemble_source("MOV , X\nCLR A\n", Program::default(), false).unwrap_err();
+        assert!(error.to_string().starts_with("Syntax error"));
+    }
+
+    #[test]
+    fn test_suggest_relaxed() {
+        // a label without a colon is only accepted in relaxed mode
+        let legacy = "START\n  MOV MC0,X\n  JMP START\n";
+        let (prog, result) = assemble_with(legacy, Program::default(), false);
+        assert!(result.is_err());
+        assert!(relaxed_gets_further(legacy, Program::default(), prog.line));
+
+        // relaxed mode can't help with a genuine mistake
+        let broken = "CLR A\n  MOV , X\n";
+        let (prog, result) = assemble_with(broken, Program::default(), false);
+        assert!(result.is_err());
+        assert!(!relaxed_gets_further(broken, Program::default(), prog.line));
+    }
+
+    #[test]
+    fn test_error_column() {
+        let (prog, result) = assemble_with("NOP\n  MOV FOO, X\n", Program::default(), false);
+        assert!(result.is_err());
+        assert_eq!(prog.line, 1);
+        assert_eq!(prog.column, Some(6..9));
+    }
+
+    #[test]
+    fn test_stdio_dest() -> color_eyre::Result<()> {
+        assert_eq!(default_dest(Path::new("-"), Format::Bin)?, Path::new("-"));
+        assert!(is_stdio(Path::new("-")));
+        assert!(!is_stdio(Path::new("./-")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_dest() -> color_eyre::Result<()> {
+        assert_eq!(
+            default_dest(Path::new("dsp/prog.asm"), Format::Bin)?,
+            Path::new("dsp/prog.bin")
+        );
+        assert_eq!(
+            default_dest(Path::new("prog.asm"), Format::CArray)?,
+            Path::new("prog.h")
+        );
+        assert_eq!(
+            default_dest(Path::new("prog"), Format::Bin)?,
+            Path::new("prog.bin")
+        );
+
+        assert_eq!(
+            default_dest(Path::new("https://example.com/dsp/prog.asm"), Format::Bin)?,
+            Path::new("prog.bin")
+        );
+
+        // never overwrite the source
+        assert!(default_dest(Path::new("prog.bin"), Format::Bin).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_read_source_url() -> color_eyre::Result<()> {
+        use std::{io::BufRead, net::TcpListener, thread};
+
+        // a one-shot server that answers every request with a tiny program
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = thread::spawn(move || -> io::Result<()> {
+            let (stream, _) = listener.accept()?;
+            let mut reader = io::BufReader::new(stream.try_clone()?);
+            let mut line = String::new();
+            while reader.read_line(&mut line)? > 2 {
+                line.clear();
+            }
+
+            let body = "CLR A\nENDI";
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        });
+
+        let url = PathBuf::from(format!("http://{addr}/prog.asm"));
+        let prog = assemble_file(&url, Program::default(), false, true)?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0xF800_0000]);
+        server.join().unwrap()?;
+
+        // nothing is listening here any more
+        assert!(read_source(&url, true).is_err());
+
+        Ok(())
+    }
+}
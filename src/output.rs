@@ -0,0 +1,581 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Writers for the various output file formats
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use color_eyre::eyre::eyre;
+
+use crate::emitter::WordSource;
+
+/// Size of the SCU DSP's program RAM, in words
+pub const PROGRAM_RAM_WORDS: usize = 256;
+
+/// Output file formats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Raw binary, as loaded into program RAM
+    #[default]
+    Bin,
+
+    /// A C array, for embedding in homebrew source
+    CArray,
+
+    /// A Rust const array, for embedding in tools and emulators
+    RustArray,
+
+    /// Motorola S-records, for toolchains and flashers that expect them
+    Srec,
+
+    /// One hex word per line, for initialising a ROM with Verilog's `$readmemh` in FPGA
+    /// recreations and simulators
+    Verilog,
+
+    /// Intel HEX records, for toolchains and flashers that expect them
+    Ihex,
+}
+
+impl Format {
+    /// Extension of the file written when no destination is given
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Bin => "bin",
+            Format::CArray => "h",
+            Format::RustArray => "rs",
+            Format::Srec => "srec",
+            Format::Verilog => "mem",
+            Format::Ihex => "hex",
+        }
+    }
+
+    /// Name given to the array by formats that declare one, unless overridden. It's derived from
+    /// the name of the output file, so `sine_table.h` declares `sine_table`, with anything that
+    /// can't go in an identifier replaced by `_`. Rust arrays are constants, so are uppercased.
+    pub fn default_symbol(self, dest: &Path) -> String {
+        let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+        let mut symbol: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if symbol.trim_matches('_').is_empty() {
+            symbol = "dsp_program".into();
+        } else if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+            symbol.insert(0, '_');
+        }
+
+        match self {
+            Format::RustArray => symbol.to_uppercase(),
+            _ => symbol,
+        }
+    }
+}
+
+/// Headers that can be written before the program in binary output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Header {
+    /// Just the program
+    #[default]
+    None,
+
+    /// Four little-endian words: magic, version, program length in words and entry point
+    Simple,
+}
+
+/// Default magic number of the simple header, "SDSP" when read as bytes
+pub const HEADER_MAGIC: u32 = u32::from_le_bytes(*b"SDSP");
+
+/// Default version of the simple header
+pub const HEADER_VERSION: u32 = 1;
+
+/// Size of the simple header, in words
+pub const HEADER_WORDS: usize = 4;
+
+/// Builds the simple header for a program of `len` words that starts executing at `entry`
+pub fn simple_header(magic: u32, version: u32, len: usize, entry: u32) -> [u32; HEADER_WORDS] {
+    [magic, version, len as u32, entry]
+}
+
+/// Returns the program from words that may start with a simple header. The header is only
+/// recognised if it has the given magic number and its length matches the rest of the words.
+pub fn strip_header(words: &[u32], magic: u32) -> &[u32] {
+    match words {
+        [first, _, len, _, program @ ..] if *first == magic && *len as usize == program.len() => {
+            program
+        }
+        _ => words,
+    }
+}
+
+/// Parses a number given on the command line, in decimal or with a `0x` prefix, in hex
+pub fn parse_u32(value: &str) -> Result<u32, String> {
+    let parsed = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|error| format!("'{value}' is not a valid 32-bit number: {error}"))
+}
+
+/// Parses a hex number given on the command line, with or without a `0x` prefix
+pub fn parse_hex_u32(value: &str) -> Result<u32, String> {
+    let hex = value
+        .strip_prefix("0x")
+        .or(value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u32::from_str_radix(hex, 16)
+        .map_err(|error| format!("'{value}' is not a valid 32-bit hex number: {error}"))
+}
+
+/// Case of the hex digits in textual output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HexCase {
+    #[default]
+    Upper,
+    Lower,
+}
+
+impl HexCase {
+    /// Formats a value as hex, zero padded to `digits` digits, without a prefix
+    pub fn format(self, value: u32, digits: usize) -> String {
+        match self {
+            HexCase::Upper => format!("{value:0digits$X}"),
+            HexCase::Lower => format!("{value:0digits$x}"),
+        }
+    }
+}
+
+/// How textual output such as listings lines up the instructions of a bundle into columns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Columns {
+    /// Columns start on multiples of this many characters
+    pub tab_width: usize,
+
+    /// Separate columns with tabs instead of spaces
+    pub use_tabs: bool,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            use_tabs: false,
+        }
+    }
+}
+
+impl Columns {
+    /// Joins `fields` so that each starts on a tab stop, given that the line is already `start`
+    /// characters in. There's always at least one space (or a tab) between fields.
+    pub fn join(&self, start: usize, fields: &[String]) -> String {
+        let tab_width = self.tab_width.max(1);
+        let mut out = String::new();
+        let mut column = start;
+
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                let stop = (column / tab_width + 1) * tab_width;
+                if self.use_tabs {
+                    out.push('\t');
+                } else {
+                    out += &" ".repeat(stop - column);
+                }
+                column = stop;
+            }
+            out += field;
+            column += field.len();
+        }
+
+        out
+    }
+}
+
+/// Formats the program as a C array named `name`, one word per line
+pub fn c_array(words: &[u32], name: &str, hex_case: HexCase) -> String {
+    let mut out = format!("const uint32_t {name}[] = {{\n");
+    for word in words {
+        out += &format!("    0x{},\n", hex_case.format(*word, 8));
+    }
+    out += "};\n";
+    out
+}
+
+/// Produces a listing of the program with the address, word and original source line of each word
+/// (see [`crate::emitter::Program::word_sources`]). Bundles continued over several lines show
+/// the line they start on.
+pub fn source_listing(words: &[u32], sources: &[WordSource], source: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut out = String::from("; addr  word      line  source\n");
+
+    for (addr, (word, source)) in words.iter().zip(sources).enumerate() {
+        let line = match source {
+            WordSource::Bundle(line) | WordSource::Data(line) => *line,
+            WordSource::Padding => {
+                out += &format!("${addr:02X}    {word:08X}        (padding)\n");
+                continue;
+            }
+        };
+        let text = lines.get(line as usize).copied().unwrap_or("").trim_end();
+        out += &format!("${addr:02X}    {word:08X}  {:>4}  {text}\n", line + 1);
+    }
+
+    out
+}
+
+/// Formats the program as a Rust const array named `name`
+pub fn rust_array(words: &[u32], name: &str, hex_case: HexCase) -> String {
+    let values: Vec<String> = words
+        .iter()
+        .map(|word| format!("0x{}", hex_case.format(*word, 8)))
+        .collect();
+    format!(
+        "pub const {name}: [u32; {}] = [{}];\n",
+        words.len(),
+        values.join(", ")
+    )
+}
+
+/// Formats the program for Verilog's `$readmemh`: one word per line, as 8 hex digits with no
+/// prefix, starting from address 0
+pub fn readmemh(words: &[u32], hex_case: HexCase) -> String {
+    words
+        .iter()
+        .map(|word| hex_case.format(*word, 8) + "\n")
+        .collect()
+}
+
+/// Bytes of data in each S-record
+const SREC_DATA_BYTES: usize = 16;
+
+/// Formats a single S-record of the given type, with its byte count and checksum
+fn srec_record(kind: u8, addr: u32, addr_bytes: usize, data: &[u8], hex_case: HexCase) -> String {
+    let count = (addr_bytes + data.len() + 1) as u8;
+    let addr = &addr.to_be_bytes()[4 - addr_bytes..];
+
+    // the checksum is the ones' complement of the low byte of the sum of everything but the type
+    let sum = addr
+        .iter()
+        .chain(data)
+        .fold(count, |sum, byte| sum.wrapping_add(*byte));
+
+    let mut out = format!("S{kind}{}", hex_case.format(count.into(), 2));
+    for byte in addr.iter().chain(data).chain(&[!sum]) {
+        out += &hex_case.format((*byte).into(), 2);
+    }
+    out + "\n"
+}
+
+/// Formats the program's bytes as Motorola S-records: an S0 header holding `name`, data records
+/// and a terminator giving the `entry` address. Addresses are in bytes. S1/S9 records with 16-bit
+/// addresses are used when everything fits, otherwise S2/S8 with 24-bit addresses.
+pub fn srec(bytes: &[u8], name: &str, entry: u32, hex_case: HexCase) -> String {
+    let wide = bytes.len() > 0x1_0000 || entry > 0xFFFF;
+    let (data_kind, end_kind, addr_bytes) = if wide { (2, 8, 3) } else { (1, 9, 2) };
+
+    let mut out = srec_record(0, 0, 2, name.as_bytes(), hex_case);
+    for (i, chunk) in bytes.chunks(SREC_DATA_BYTES).enumerate() {
+        let addr = (i * SREC_DATA_BYTES) as u32;
+        out += &srec_record(data_kind, addr, addr_bytes, chunk, hex_case);
+    }
+    out += &srec_record(end_kind, entry, addr_bytes, &[], hex_case);
+    out
+}
+
+/// Bytes of data in each Intel HEX record
+const IHEX_DATA_BYTES: usize = 16;
+
+/// Formats a single Intel HEX record of the given type, with its byte count and checksum
+fn ihex_record(kind: u8, addr: u16, data: &[u8], hex_case: HexCase) -> String {
+    let header = [data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+
+    // the checksum is the two's complement of the low byte of the sum of every other byte
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    let mut out = String::from(":");
+    for byte in header.iter().chain(data).chain(&[sum.wrapping_neg()]) {
+        out += &hex_case.format((*byte).into(), 2);
+    }
+    out + "\n"
+}
+
+/// Formats the program's bytes as Intel HEX: data records addressed in bytes from 0, followed by an
+/// end of file record. An extended linear address record is written whenever the data crosses a
+/// 64 KiB boundary, though a program that fits in program RAM never does.
+pub fn ihex(bytes: &[u8], hex_case: HexCase) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+
+    for (i, chunk) in bytes.chunks(IHEX_DATA_BYTES).enumerate() {
+        let addr = (i * IHEX_DATA_BYTES) as u32;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            out += &ihex_record(4, 0, &(upper as u16).to_be_bytes(), hex_case);
+        }
+        out += &ihex_record(0, addr as u16, chunk, hex_case);
+    }
+    out += &ihex_record(1, 0, &[], hex_case);
+    out
+}
+
+/// Splits the program into banks of `bank_words` words, for loaders that fill program RAM one bank
+/// at a time. The last bank may be short, unless a `pad` word is given to fill it out to full size.
+pub fn split_banks(
+    words: &[u32],
+    bank_words: usize,
+    pad: Option<u32>,
+) -> color_eyre::Result<Vec<Vec<u32>>> {
+    if bank_words == 0 {
+        return Err(eyre!("Error: Bank size must be at least one word"));
+    }
+    if words.len() > PROGRAM_RAM_WORDS {
+        return Err(eyre!(
+            "Error: Program is {} words, which doesn't fit in the {PROGRAM_RAM_WORDS} words of \
+            program RAM",
+            words.len()
+        ));
+    }
+
+    let mut banks: Vec<Vec<u32>> = words.chunks(bank_words).map(<[u32]>::to_vec).collect();
+    if let (Some(pad), Some(last)) = (pad, banks.last_mut()) {
+        last.resize(bank_words, pad);
+    }
+    Ok(banks)
+}
+
+/// Returns the file a bank is written to, e.g. `out.bank0.bin` for bank 0 of `out.bin`
+pub fn bank_path(dest: &Path, index: usize) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!("{stem}.bank{index}.bin"))
+}
+
+/// Lists the file each bank was written to and its load address, one bank per line
+pub fn bank_manifest(dest: &Path, banks: &[Vec<u32>], bank_words: usize) -> String {
+    let mut out = String::from("; file load-address\n");
+    for index in 0..banks.len() {
+        let path = bank_path(dest, index);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        out += &format!("{name} ${:02X}\n", index * bank_words);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_array_hex_case() {
+        let words = [0x0002_0000, 0xF800_0000];
+
+        assert_eq!(
+            c_array(&words, "dsp_program", HexCase::Lower),
+            "const uint32_t dsp_program[] = {\n    0x00020000,\n    0xf8000000,\n};\n"
+        );
+        assert_eq!(
+            c_array(&words, "dsp_program", HexCase::Upper),
+            "const uint32_t dsp_program[] = {\n    0x00020000,\n    0xF8000000,\n};\n"
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        let bundle: Vec<String> = ["MOV MUL,P", "MOV M0,A", "AD2"]
+            .iter()
+            .map(|instr| instr.to_string())
+            .collect();
+
+        let four = Columns {
+            tab_width: 4,
+            use_tabs: false,
+        };
+        let eight = Columns {
+            tab_width: 8,
+            use_tabs: false,
+        };
+        let tabs = Columns {
+            tab_width: 8,
+            use_tabs: true,
+        };
+        assert_eq!(four.join(0, &bundle), "MOV MUL,P   MOV M0,A    AD2");
+        assert_eq!(
+            eight.join(0, &bundle),
+            "MOV MUL,P       MOV M0,A        AD2"
+        );
+        assert_eq!(eight.join(2, &bundle), "MOV MUL,P     MOV M0,A        AD2");
+        assert_eq!(tabs.join(0, &bundle), "MOV MUL,P\tMOV M0,A\tAD2");
+    }
+
+    #[test]
+    fn test_simple_header() {
+        let program = [0x0002_0000, 0x0100_0000, 0xF800_0000];
+        let header = simple_header(HEADER_MAGIC, HEADER_VERSION, program.len(), 1);
+        assert_eq!(header, [0x5053_4453, 1, 3, 1]);
+
+        let words = [header.as_slice(), &program].concat();
+        assert_eq!(strip_header(&words, HEADER_MAGIC), program);
+
+        // a different magic, or a length that doesn't match, isn't a header
+        assert_eq!(strip_header(&words, 0x1234), words);
+        assert_eq!(strip_header(&words[..6], HEADER_MAGIC), &words[..6]);
+        assert_eq!(strip_header(&program, HEADER_MAGIC), program);
+    }
+
+    #[test]
+    fn test_parse_hex_u32() {
+        assert_eq!(parse_hex_u32("4ed4422d"), Ok(0x4ED4_422D));
+        assert_eq!(parse_hex_u32("0x4ED4422D"), Ok(0x4ED4_422D));
+        assert!(parse_hex_u32("0x1_0000_0000").is_err());
+        assert!(parse_hex_u32("xyz").is_err());
+    }
+
+    #[test]
+    fn test_parse_u32() {
+        assert_eq!(parse_u32("0x50534453"), Ok(0x5053_4453));
+        assert_eq!(parse_u32("42"), Ok(42));
+        assert!(parse_u32("0xZZ").is_err());
+    }
+
+    #[test]
+    fn test_default_symbol() {
+        let symbol = |format: Format, dest: &str| format.default_symbol(Path::new(dest));
+
+        assert_eq!(symbol(Format::CArray, "build/dsp_program.h"), "dsp_program");
+        assert_eq!(symbol(Format::CArray, "sine-table.v2.h"), "sine_table_v2");
+        assert_eq!(symbol(Format::CArray, "3d.h"), "_3d");
+        assert_eq!(symbol(Format::CArray, "---.h"), "dsp_program");
+        assert_eq!(symbol(Format::RustArray, "sine-table.rs"), "SINE_TABLE");
+
+        let words = [0x0002_0000, 0xF800_0000];
+        assert_eq!(
+            c_array(
+                &words,
+                &symbol(Format::CArray, "out/sine.h"),
+                HexCase::Upper
+            ),
+            "const uint32_t sine[] = {\n    0x00020000,\n    0xF8000000,\n};\n"
+        );
+    }
+
+    #[test]
+    fn test_rust_array() {
+        // the expected output, pasted in as code to show that it compiles
+        #[rustfmt::skip]
+        pub const DSP_PROGRAM: [u32; 2] = [0x00020000, 0xF8000000];
+
+        let words = [0x0002_0000, 0xF800_0000];
+        assert_eq!(DSP_PROGRAM, words);
+        assert_eq!(
+            rust_array(&words, "DSP_PROGRAM", HexCase::Upper),
+            "pub const DSP_PROGRAM: [u32; 2] = [0x00020000, 0xF8000000];\n"
+        );
+    }
+
+    #[test]
+    fn test_readmemh() {
+        let words = [0x0002_0000, 0xF800_0000];
+        assert_eq!(readmemh(&words, HexCase::Upper), "00020000\nF8000000\n");
+        assert_eq!(readmemh(&words, HexCase::Lower), "00020000\nf8000000\n");
+    }
+
+    #[test]
+    fn test_ihex() {
+        let bytes = [0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0xF8];
+        assert_eq!(
+            ihex(&bytes, HexCase::Upper),
+            ":0800000000000200000000F8FE\n:00000001FF\n"
+        );
+
+        // 64 KiB and a bit needs an extended address record
+        let bytes = vec![0; 0x1_0010];
+        let text = ihex(&bytes, HexCase::Upper);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 0x1001 + 2);
+        assert_eq!(lines[0x1000], ":020000040001F9");
+        assert_eq!(lines[0x1001], format!(":10000000{}F0", "00".repeat(16)));
+    }
+
+    /// Checks an S-record's byte count and checksum, returning its type, address and data
+    fn parse_srec(line: &str) -> (char, u32, Vec<u8>) {
+        let kind = line.chars().nth(1).unwrap();
+        let bytes: Vec<u8> = (2..line.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(bytes[0] as usize, bytes.len() - 1, "{line}");
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(sum, 0xFF, "{line}");
+
+        let addr_bytes = match kind {
+            '0' | '1' | '9' => 2,
+            _ => 3,
+        };
+        let addr = bytes[1..=addr_bytes]
+            .iter()
+            .fold(0, |addr, byte| addr << 8 | u32::from(*byte));
+        (kind, addr, bytes[addr_bytes + 1..bytes.len() - 1].to_vec())
+    }
+
+    #[test]
+    fn test_srec() {
+        let bytes = [0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0xF8];
+        assert_eq!(
+            srec(&bytes, "DSP", 4, HexCase::Upper),
+            "S006000044535012\nS10B000000000200000000F8FA\nS9030004F8\n"
+        );
+
+        // a longer program round trips through the records
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = srec(&bytes, "dsp_program", 0, HexCase::Lower);
+        let records: Vec<_> = text.lines().map(parse_srec).collect();
+        assert_eq!(records.len(), 1 + 16 + 1);
+        assert_eq!(records[0], ('0', 0, b"dsp_program".to_vec()));
+        assert_eq!(records[17], ('9', 0, vec![]));
+
+        let mut data: Vec<u8> = Vec::new();
+        for (kind, addr, chunk) in &records[1..17] {
+            assert_eq!((*kind, *addr as usize), ('1', data.len()));
+            data.extend(chunk);
+        }
+        assert_eq!(data, bytes);
+    }
+
+    #[test]
+    fn test_split_banks() -> color_eyre::Result<()> {
+        let words: Vec<u32> = (0..130).collect();
+        let banks = split_banks(&words, 64, None)?;
+
+        assert_eq!(banks.len(), 3);
+        assert_eq!(banks[0], &words[0..64]);
+        assert_eq!(banks[1], &words[64..128]);
+        assert_eq!(banks[2], &[128, 129]);
+
+        let dest = Path::new("build/out.bin");
+        assert_eq!(bank_path(dest, 2), Path::new("build/out.bank2.bin"));
+        assert_eq!(
+            bank_manifest(dest, &banks, 64),
+            "; file load-address\nout.bank0.bin $00\nout.bank1.bin $40\nout.bank2.bin $80\n"
+        );
+
+        // padding fills out the last bank only
+        let banks = split_banks(&words, 64, Some(0xF800_0000))?;
+        assert_eq!(banks[1], &words[64..128]);
+        assert_eq!(banks[2].len(), 64);
+        assert_eq!(banks[2][..2], [128, 129]);
+        assert!(banks[2][2..].iter().all(|&word| word == 0xF800_0000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_banks_too_big() {
+        let words = vec![0; PROGRAM_RAM_WORDS + 1];
+        assert!(split_banks(&words, 64, None).is_err());
+    }
+}
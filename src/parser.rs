@@ -9,15 +9,15 @@
 // - https://en.wikipedia.org/wiki/Recursive_descent_parser#C_implementation
 // - https://github.com/maciejhirsz/logos/issues/82
 
+use std::ops::Range;
+
 use bit_ops::BitOps;
 use color_eyre::eyre::eyre;
-use log::{debug, warn};
-use logos::Lexer;
-use std::{i8, iter::Peekable};
+use log::debug;
 
 use crate::{
-    emitter::{InstrType, Program},
-    tokeniser::ScuDspToken,
+    emitter::{AssembleError, BundleLimits, Define, InstrType, MovDestination, Program, Section},
+    tokeniser::{ScuDspToken, Tokens, lex},
 };
 
 type T = ScuDspToken;
@@ -41,6 +41,9 @@ const ALU_TOKENS: &[&T] = &[
 /// All loop tokens
 const LOOP_TOKENS: &[&T] = &[&T::Btm, &T::Lps];
 
+/// All DMA tokens
+const DMA_TOKENS: &[&T] = &[&T::Dma, &T::Dmah];
+
 /// All end tokens
 const END_TOKENS: &[&T] = &[&T::End, &T::Endi];
 
@@ -61,6 +64,7 @@ const INSTR_TOKENS: &[&T] = &[
     &T::Mov,
     &T::Mvi,
     &T::Dma,
+    &T::Dmah,
     &T::Jmp,
     &T::Clr,
     &T::Btm,
@@ -69,10 +73,17 @@ const INSTR_TOKENS: &[&T] = &[
     &T::Endi,
 ];
 
-/// All SImm destination addresses
-const SIMM_DEST: &[&T] = &[
-    &T::Mc0,
+/// Tokens that start a flow control instruction
+const FLOW_CONTROL_TOKENS: &[&T] = &[&T::Jmp, &T::Btm, &T::Lps, &T::End, &T::Endi];
+
+/// Tokens that a define can alias, e.g. `SRC EQU M2`
+const REGISTER_TOKENS: &[&T] = &[
+    &T::M0,
+    &T::M1,
+    &T::M2,
+    &T::M3,
     &T::Mc0,
+    &T::Mc1,
     &T::Mc2,
     &T::Mc3,
     &T::Rx,
@@ -85,40 +96,53 @@ const SIMM_DEST: &[&T] = &[
     &T::Ct1,
     &T::Ct2,
     &T::Ct3,
+    &T::All,
+    &T::Alh,
+    &T::D0,
+    &T::Prg,
 ];
 
-#[derive(PartialEq, Eq)]
-enum MovDestination {
-    X,
-    P,
-    Y,
-    A,
+/// Condition codes for JMP and conditional MVI. These are 7 bits wide: bit 6 marks the instruction
+/// as conditional, bit 5 selects whether the flags must be set (1) or clear (0) and bits 3-0 select
+/// the flags tested (T0, C, S, Z respectively).
+pub(crate) const CONDITIONS: &[(&T, u32)] = &[
+    (&T::Nz, 0b100_0001),
+    (&T::Ns, 0b100_0010),
+    (&T::Nzs, 0b100_0011),
+    (&T::Nc, 0b100_0100),
+    (&T::Nt0, 0b100_1000),
+    (&T::Z, 0b110_0001),
+    (&T::S, 0b110_0010),
+    (&T::Zs, 0b110_0011),
+    (&T::C, 0b110_0100),
+    (&T::T0, 0b110_1000),
+];
+
+/// An immediate operand
+pub(crate) enum Imm {
+    /// A value known up front, from a number or a define
+    Value(u32),
+
+    /// The address of a label, which may not have been declared yet
+    Label(String),
 }
 
-fn accept(tok: &ScuDspToken, lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<bool> {
-    if let Some(stream) = lexer.peek() {
-        if stream.as_ref().is_ok_and(|x| tok == x) {
-            let _ = lexer.next();
-            return Ok(true);
-        }
+fn accept(tok: &ScuDspToken, lexer: &mut Tokens) -> color_eyre::Result<bool> {
+    if let Some(stream) = lexer.peek()
+        && stream.as_ref().is_ok_and(|x| tok == x)
+    {
+        let _ = lexer.next();
+        return Ok(true);
     }
 
     Ok(false)
 }
 
-fn expect(tok: &ScuDspToken, lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<bool> {
+fn expect(tok: &ScuDspToken, lexer: &mut Tokens) -> color_eyre::Result<bool> {
     if accept(tok, lexer)? {
         return Ok(true);
     }
 
-    // if we expected equals but we got newline, this is often caused by not running in relaxed
-    // mode
-    if tok == &T::Equals && token(lexer)? == T::Newline {
-        warn!(
-            "Note: If this is a legacy document, consider trying again in relaxed mode (--relaxed)."
-        );
-    }
-
     Err(eyre!(
         "Syntax error: Expected {} but got {}.",
         &tok.as_ref(),
@@ -126,8 +150,23 @@ fn expect(tok: &ScuDspToken, lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_
     ))
 }
 
+/// Expects the comma between two operands. Legacy sources sometimes leave it out, separating the
+/// operands with whitespace alone, which relaxed mode accepts.
+fn separator(lexer: &mut Tokens, relaxed: bool) -> color_eyre::Result<()> {
+    if accept(&T::Comma, lexer)? {
+        return Ok(());
+    }
+    if relaxed && !at_end_of_line(lexer)? {
+        debug!("Missing comma between operands, accepting in relaxed mode");
+        return Ok(());
+    }
+
+    expect(&T::Comma, lexer)?;
+    Ok(())
+}
+
 /// Returns, but does not remove, the token at the current position in the lexer
-fn token(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<ScuDspToken> {
+fn token(lexer: &mut Tokens) -> color_eyre::Result<ScuDspToken> {
     if let Some(stream) = lexer.peek() {
         match stream {
             Ok(tok) => Ok(tok.clone()),
@@ -139,7 +178,7 @@ fn token(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<ScuDspT
 }
 
 /// Returns, **and removes**, the token at the current position in the lexer
-fn token_pop(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<ScuDspToken> {
+fn token_pop(lexer: &mut Tokens) -> color_eyre::Result<ScuDspToken> {
     if let Some(stream) = lexer.next() {
         match stream {
             Ok(tok) => Ok(tok.clone()),
@@ -150,9 +189,11 @@ fn token_pop(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<Scu
     }
 }
 
-/// Converts token to string for debuugging
-fn token_str(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<String> {
+/// Converts token to string for debuugging. Since this is used to say what went wrong, the token
+/// is also marked as the one the error points at.
+fn token_str(lexer: &mut Tokens) -> color_eyre::Result<String> {
     let tok = token(lexer)?;
+    lexer.mark();
 
     match &tok {
         T::Label(lab) => Ok(format!("{} '{}'", tok.as_ref(), lab)),
@@ -162,72 +203,221 @@ fn token_str(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<Str
     }
 }
 
-fn num(lexer: &mut Peekable<Lexer<ScuDspToken>>) -> color_eyre::Result<u32> {
+/// Returns true if the lexer is at the end of a line (or the end of the input), without consuming
+/// the newline so the bundle still gets flushed
+fn at_end_of_line(lexer: &mut Tokens) -> color_eyre::Result<bool> {
+    if lexer.peek().is_none() {
+        return Ok(true);
+    }
+
+    Ok(token(lexer)? == T::Newline)
+}
+
+/// Rejects a bit range after a destination register (e.g. `CT0[0:3]`). The SCU DSP always writes
+/// the whole destination, so there's no way to encode these.
+fn reject_bit_range(lexer: &mut Tokens) -> color_eyre::Result<()> {
+    if lexer.peek().is_some() && token(lexer)? == T::LBracket {
+        return Err(eyre!(
+            "Syntax error: Partial loads not supported; the SCU DSP always writes the whole \
+            destination register"
+        ));
+    }
+
+    Ok(())
+}
+
+fn num(lexer: &mut Tokens) -> color_eyre::Result<u32> {
     if !token(lexer)?.is_number() {
         return Err(eyre!("Syntax error: Expected number"));
     }
 
     match token_pop(lexer)? {
-        T::Num(mut num_str) => {
-            if num_str.starts_with('$') {
-                // hex
-                num_str.remove(0);
-                Ok(u32::from_str_radix(num_str.as_str(), 16)?)
-            } else if num_str.starts_with('#') {
-                // decimal?
-                num_str.remove(0);
-                return Ok(num_str.parse()?);
-            } else if num_str.starts_with('%') {
-                // binary
-                num_str.remove(0);
-                return Ok(u32::from_str_radix(num_str.as_str(), 2)?);
-            } else {
-                // also decimal
-                return Ok(num_str.parse()?);
+        T::Num(num_str) => parse_num(&num_str),
+        _ => Err(eyre!("Syntax error: Expected number")),
+    }
+}
+
+/// Parses a number literal. Negative numbers are returned as their two's complement bit pattern,
+/// for the caller to bounds check against its field.
+fn parse_num(num_str: &str) -> color_eyre::Result<u32> {
+    let (digits, radix, name) = if let Some(digits) = num_str.strip_prefix('$') {
+        (digits, 16, "hexadecimal")
+    } else if let Some(digits) = num_str.strip_prefix('#') {
+        (digits, 10, "decimal")
+    } else if let Some(digits) = num_str.strip_prefix('%') {
+        (digits, 2, "binary")
+    } else {
+        // also decimal
+        (num_str, 10, "decimal")
+    };
+
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+
+    // underscores may separate digits, e.g. $DEAD_BEEF
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(eyre!(
+            "Syntax error: Misplaced '_' in '{num_str}'; underscores can only go between digits"
+        ));
+    }
+    let digits = digits.replace('_', "");
+
+    match negative {
+        true => {
+            let value = radix_num(num_str, &digits, radix, name)?;
+            if value > i32::MIN.unsigned_abs() {
+                return Err(eyre!("Syntax error: '{num_str}' doesn't fit in 32 bits"));
             }
+            Ok(value.wrapping_neg())
         }
-        _ => Err(eyre!("Syntax error: Expected number")),
+        false => radix_num(num_str, &digits, radix, name),
+    }
+}
+
+/// Parses the digits of a number literal in the given radix, naming the first bad digit if there is
+/// one
+fn radix_num(literal: &str, digits: &str, radix: u32, name: &str) -> color_eyre::Result<u32> {
+    if let Some(bad) = digits.chars().find(|c| !c.is_digit(radix)) {
+        return Err(eyre!(
+            "Syntax error: '{bad}' is not a valid {name} digit in '{literal}'"
+        ));
+    }
+
+    u32::from_str_radix(digits, radix)
+        .map_err(|_| eyre!("Syntax error: '{literal}' doesn't fit in 32 bits"))
+}
+
+/// Pops an operand token, replacing it with the register it stands for if it's a define aliasing
+/// one. Numeric defines are left alone for the caller to deal with.
+fn operand(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<ScuDspToken> {
+    lexer.mark();
+    match token_pop(lexer)? {
+        T::Ident(name) if prog.is_token_alias(&name) => prog.resolve_token(name),
+        tok => Ok(tok),
+    }
+}
+
+/// Parses an immediate value, which is either a number, a define or a label. Identifiers that
+/// aren't defines are assumed to be labels, since those may be declared later on.
+fn imm(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<Imm> {
+    lexer.mark();
+    if let T::Ident(name) = token(lexer)? {
+        lexer.next();
+        if prog.is_define(&name) {
+            return Ok(Imm::Value(prog.resolve_define(name)?));
+        }
+        return Ok(Imm::Label(name));
+    }
+
+    Ok(Imm::Value(num(lexer)?))
+}
+
+/// Fills in an immediate operand in the low bits of the current bundle. `max` is the largest value
+/// that fits in the field, and `mask` covers the field's bits.
+fn emit_imm(value: Imm, max: u32, mask: u32, prog: &mut Program) -> color_eyre::Result<()> {
+    match value {
+        Imm::Value(value) => {
+            // callers have already bounds checked the value, so this only drops the sign bits
+            prog.emit_operand(value & mask);
+            Ok(())
+        }
+        Imm::Label(label) => prog.reference_label(label, max),
+    }
+}
+
+/// Parses a condition code if there is one, returning its encoding
+fn condition(lexer: &mut Tokens) -> color_eyre::Result<Option<u32>> {
+    let tok = token(lexer)?;
+    match CONDITIONS.iter().find(|(cond, _)| **cond == tok) {
+        Some((_, bits)) => {
+            lexer.next();
+            Ok(Some(*bits))
+        }
+        None => Ok(None),
     }
 }
 
 // ALU control commands
-fn alu(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_eyre::Result<()> {
+/// Returns the opcode (bits 29-26) of an ALU operation, if the token is one
+pub(crate) fn alu_opcode(op: &ScuDspToken) -> Option<u32> {
+    match op {
+        T::Nop => Some(0b0000),
+        T::And => Some(0b0001),
+        T::Or => Some(0b0010),
+        T::Xor => Some(0b0011),
+        T::Add => Some(0b0100),
+        T::Sub => Some(0b0101),
+        T::Ad2 => Some(0b0110),
+        T::Sr => Some(0b1000),
+        T::Rr => Some(0b1001),
+        T::Sl => Some(0b1010),
+        T::Rl => Some(0b1011),
+        T::Rl8 => Some(0b1111),
+        _ => None,
+    }
+}
+
+fn alu(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
     debug!("Parse ALU instr");
-    if accept(&T::Nop, lexer)? {
-        prog.emit(0);
-    } else if accept(&T::And, lexer)? {
-        prog.emit_bit(26);
-    } else if accept(&T::Or, lexer)? {
-        prog.emit_bit(27);
-    } else if accept(&T::Xor, lexer)? {
-        prog.emit_bits(vec![26, 27]);
-    } else if accept(&T::Add, lexer)? {
-        prog.emit_bit(28);
-    } else if accept(&T::Sub, lexer)? {
-        prog.emit_bits(vec![26, 28]);
-    } else if accept(&T::Ad2, lexer)? {
-        prog.emit_bits(vec![27, 28]);
-    } else if accept(&T::Sr, lexer)? {
-        prog.emit_bit(29);
-    } else if accept(&T::Rr, lexer)? {
-        prog.emit_bits(vec![26, 29]);
-    } else if accept(&T::Sl, lexer)? {
-        prog.emit_bits(vec![27, 29]);
-    } else if accept(&T::Rl, lexer)? {
-        prog.emit_bits(vec![26, 27, 29]);
-    } else if accept(&T::Rl8, lexer)? {
-        prog.emit_bits(vec![26, 27, 28, 29]);
-    } else {
+    let Some(opcode) = alu_opcode(&token(lexer)?) else {
         return Err(eyre!(
             "Syntax error: Could not parse ALU command near {}",
             token_str(lexer)?
         ));
-    }
+    };
+    lexer.next();
+
+    prog.emit(opcode << 26);
     prog.register_emitted(InstrType::Alu);
 
     Ok(())
 }
 
+/// Encodes an X-bus or Y-bus MOV from `src` into `dest`, which is one of X, P, Y or A
+pub(crate) fn emit_bus_mov(
+    src: &ScuDspToken,
+    dest: &ScuDspToken,
+    prog: &mut Program,
+) -> color_eyre::Result<()> {
+    // a define aliasing a register stands for that register; anything else that's an identifier
+    // (a numeric define, or something undeclared) gets explained by resolve_token()
+    let resolved;
+    let src = match src {
+        T::Ident(name) => {
+            resolved = prog.resolve_token(name.clone())?;
+            &resolved
+        }
+        _ => src,
+    };
+
+    match (src, dest) {
+        (T::Mul, T::P) => {
+            prog.emit_bit(24);
+            // this is an X-bus instr (datasheet pp. 108, pdf pp. 124)
+            prog.register_emitted(InstrType::XBus);
+            prog.register_destination(MovDestination::P);
+            Ok(())
+        }
+        (T::Alu, T::A) => {
+            prog.emit_bit(18);
+            // this is a Y-bus instruction (datasheet pp. 114, pdf pp. 114)
+            prog.register_emitted(InstrType::YBus);
+            prog.register_destination(MovDestination::A);
+            Ok(())
+        }
+        (_, T::X) => emit_mov(src, MovDestination::X, prog),
+        (_, T::P) => emit_mov(src, MovDestination::P, prog),
+        (_, T::Y) => emit_mov(src, MovDestination::Y, prog),
+        _ => Err(eyre!(
+            "Syntax error: Illegal bus MOV from {} to {}",
+            src.as_ref(),
+            dest.as_ref()
+        )),
+    }
+}
+
 fn emit_mov(
     address: &ScuDspToken,
     mov: MovDestination,
@@ -257,157 +447,251 @@ fn emit_mov(
         panic!("Internal error: Unreachable branch in emit_mov calc offset");
     };
 
-    match mov {
-        MovDestination::X => prog.register_emitted(InstrType::XBus),
-        MovDestination::P => prog.register_emitted(InstrType::XBus),
-        MovDestination::Y => prog.register_emitted(InstrType::YBus),
-        MovDestination::A => prog.register_emitted(InstrType::YBus),
-    }
-
-    match address {
-        ScuDspToken::M0 => {
-            // DATA RAM0
-            prog.emit(opcode); // 000
-        }
-        ScuDspToken::M1 => {
-            // DATA RAM1
-            prog.emit(opcode.set_bit(offset)); // 001
-        }
-        ScuDspToken::M2 => {
-            // DATA RAM2
-            prog.emit(opcode.set_bit(offset + 1)); // 010
-        }
-        ScuDspToken::M3 => {
-            // DATA RAM3
-            prog.emit(opcode.set_bit(offset).set_bit(offset + 1)); // 011
-        }
-        ScuDspToken::Mc0 => {
-            // DATA RAM0, CT0++
-            prog.emit(opcode.set_bit(offset + 2)); // 100
-        }
-        ScuDspToken::Mc1 => {
-            // DATA RAM1, CT1++
-            prog.emit(opcode.set_bit(offset + 2).set_bit(offset)); // 101
-        }
-        ScuDspToken::Mc2 => {
-            // DATA RAM2, CT2++
-            prog.emit(opcode.set_bit(offset + 2).set_bit(offset + 1)); // 110
-        }
-        ScuDspToken::Mc3 => {
-            // DATA RAM3, CT3++
-            prog.emit(
-                opcode
-                    .set_bit(offset + 2)
-                    .set_bit(offset + 1)
-                    .set_bit(offset),
-            ); // 111
-        }
+    // work out the source before recording anything, so a rejected MOV leaves the bundle as it was
+    let select: u32 = match address {
+        ScuDspToken::M0 => 0b000,  // DATA RAM0
+        ScuDspToken::M1 => 0b001,  // DATA RAM1
+        ScuDspToken::M2 => 0b010,  // DATA RAM2
+        ScuDspToken::M3 => 0b011,  // DATA RAM3
+        ScuDspToken::Mc0 => 0b100, // DATA RAM0, CT0++
+        ScuDspToken::Mc1 => 0b101, // DATA RAM1, CT1++
+        ScuDspToken::Mc2 => 0b110, // DATA RAM2, CT2++
+        ScuDspToken::Mc3 => 0b111, // DATA RAM3, CT3++
         _ => {
             return Err(eyre!(
                 "Syntax error: Illegal MOV destination address, got: {}",
                 address.as_ref()
             ));
         }
+    };
+
+    match mov {
+        MovDestination::X => prog.register_emitted(InstrType::XBus),
+        MovDestination::P => prog.register_emitted(InstrType::XBus),
+        MovDestination::Y => prog.register_emitted(InstrType::YBus),
+        MovDestination::A => prog.register_emitted(InstrType::YBus),
     }
+    prog.register_destination(mov);
+    prog.emit(opcode | (select << offset));
 
     Ok(())
 }
 
+/// Sources of MOV [s], [d], as per the D1-bus control section of the manual
+pub(crate) fn d1_source(source: &ScuDspToken) -> Option<u32> {
+    match source {
+        T::M0 => Some(0b0000),
+        T::M1 => Some(0b0001),
+        T::M2 => Some(0b0010),
+        T::M3 => Some(0b0011),
+        T::Mc0 => Some(0b0100),
+        T::Mc1 => Some(0b0101),
+        T::Mc2 => Some(0b0110),
+        T::Mc3 => Some(0b0111),
+        T::All => Some(0b1001),
+        T::Alh => Some(0b1010),
+        _ => None,
+    }
+}
+
+/// Returns the D1-bus destination select bits (11-8) for a register, if it can be written over the
+/// D1-bus
+pub(crate) fn d1_dest(dest: &ScuDspToken) -> Option<u32> {
+    match dest {
+        T::Mc0 => Some(0b0000),
+        T::Mc1 => Some(0b0001),
+        T::Mc2 => Some(0b0010),
+        T::Mc3 => Some(0b0011),
+        T::Rx => Some(0b0100),
+        T::Pl => Some(0b0101),
+        T::Ra0 => Some(0b0110),
+        T::Wa0 => Some(0b0111),
+        T::Lop => Some(0b1010),
+        T::Top => Some(0b1011),
+        T::Ct0 => Some(0b1100),
+        T::Ct1 => Some(0b1101),
+        T::Ct2 => Some(0b1110),
+        T::Ct3 => Some(0b1111),
+        _ => None,
+    }
+}
+
 fn emit_mov_simm(
     imm: &ScuDspToken,
-    lexer: &mut Peekable<Lexer<ScuDspToken>>,
+    lexer: &mut Tokens,
     prog: &mut Program,
 ) -> color_eyre::Result<()> {
     debug!("Parse SImm MOV instr");
 
     let dest = token_pop(lexer)?;
     debug!("simm; source: {:?}, dest: {:?}", imm, dest);
+    let Some(dest_bits) = d1_dest(&dest) else {
+        return Err(eyre!(
+            "Syntax error: Illegal destination for MOV SImm, got: {}; expected MC0-MC3, RX, PL, \
+            RA0, WA0, LOP, TOP or CT0-CT3",
+            dest.as_ref()
+        ));
+    };
+    reject_bit_range(lexer)?;
+    if dest == T::Lop {
+        prog.mark_lop_loaded();
+    }
 
-    let value = if imm.is_ident() {
+    let value = match imm {
         // we expect this to be a define, so let's resolve it
-        match imm {
-            T::Ident(lab) => prog.resolve_define(lab.to_string())?,
-            _ => {
-                panic!("Internal error: Should have been an ident!");
-            }
+        T::Ident(name) => prog.resolve_define(name.clone())?,
+        T::Num(num_str) => parse_num(num_str)?,
+        _ => {
+            return Err(eyre!(
+                "Syntax error: Expected an immediate value for MOV SImm, got: {}",
+                imm.as_ref()
+            ));
         }
-    } else {
-        num(lexer)?
     };
 
-    if value >= i8::MAX as u32 {
+    let signed = value as i32;
+    if !(i8::MIN as i32..=i8::MAX as i32).contains(&signed) {
+        return Err(eyre!(
+            "Error: '{signed}' will not fit in signed 8-bit immediate value (in MOV SImm, [d])"
+        ));
+    }
+
+    // D1-bus control 01 selects an immediate source, manual pp. 117
+    prog.emit(0_u32.set_bit(12) | (dest_bits << 8) | (value & 0xFF));
+    prog.register_emitted(InstrType::D1Bus);
+
+    Ok(())
+}
+
+/// Parses the destination of MOV [s], [d] over the D1 bus and emits it. The source must be one
+/// of [`d1_source`].
+fn emit_d1_mov(
+    source: &ScuDspToken,
+    lexer: &mut Tokens,
+    prog: &mut Program,
+) -> color_eyre::Result<()> {
+    let Some(src_bits) = d1_source(source) else {
+        return Err(eyre!(
+            "Syntax error: Illegal source for MOV [s], [d], got: {}",
+            source.as_ref()
+        ));
+    };
+
+    let dest = operand(lexer, prog)?;
+    debug!("d1 mov; source: {:?}, dest: {:?}", source, dest);
+    let Some(dest_bits) = d1_dest(&dest) else {
+        let expected = if matches!(source, T::All | T::Alh) {
+            "it can only be moved over the D1 bus, to"
+        } else {
+            "expected X, P, Y,"
+        };
         return Err(eyre!(
-            "Error: '{value}' will not fit in signed 8-bit immediate value (in MOV SImm, [d])"
+            "Syntax error: Illegal destination for MOV {}, got: {}; {expected} MC0-MC3, RX, PL, \
+            RA0, WA0, LOP, TOP or CT0-CT3",
+            source.as_ref().to_uppercase(),
+            dest.as_ref()
         ));
+    };
+    reject_bit_range(lexer)?;
+    if dest == T::Lop {
+        prog.mark_lop_loaded();
     }
 
-    // let word = 0_u32.set_bits_exact(value as i8, 8, 0);
+    // D1-bus control 11 selects a data RAM or ALU source, manual pp. 117
+    prog.emit(0_u32.set_bit(13).set_bit(12) | (dest_bits << 8) | src_bits);
+    prog.register_emitted(InstrType::D1Bus);
+
+    Ok(())
+}
+
+/// Expects the only destination `src` can be moved to: MUL can only go to P, and ALU only to A. The
+/// two are easy to mix up, so swapping them gets a hint with the right pairings.
+fn expect_fixed_dest(
+    src: &ScuDspToken,
+    dest: &ScuDspToken,
+    lexer: &mut Tokens,
+) -> color_eyre::Result<()> {
+    let (other_src, other_dest) = if *src == T::Mul {
+        (T::Alu, T::A)
+    } else {
+        (T::Mul, T::P)
+    };
 
-    // TODO
+    if token(lexer)? == other_dest {
+        let name = |tok: &ScuDspToken| tok.as_ref().to_uppercase();
+        return Err(eyre!(
+            "Syntax error: {} can only be moved to {}, not {}. Did you mean MOV {},{} (or MOV \
+            {},{})?",
+            name(src),
+            name(dest),
+            name(&other_dest),
+            name(src),
+            name(dest),
+            name(&other_src),
+            name(&other_dest)
+        ));
+    }
 
+    expect(dest, lexer)?;
     Ok(())
 }
 
 // MOV instructions
-fn mov(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_eyre::Result<()> {
+fn mov(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
     debug!("Parse bus control instr");
     if accept(&T::Mov, lexer)? {
         // MOV MUL, P
         if accept(&T::Mul, lexer)? {
-            expect(&T::Comma, lexer)?;
-            expect(&T::P, lexer)?;
-            prog.emit_bit(24);
-            // this is an X-bus instr (datasheet pp. 108, pdf pp. 124)
-            prog.register_emitted(InstrType::XBus);
-            return Ok(());
+            separator(lexer, relaxed)?;
+            expect_fixed_dest(&T::Mul, &T::P, lexer)?;
+            return emit_bus_mov(&T::Mul, &T::P, prog);
         }
 
         // MOV ALU, A
         if accept(&T::Alu, lexer)? {
-            expect(&T::Comma, lexer)?;
-            expect(&T::A, lexer)?;
-            prog.emit_bit(18);
-            // this is a Y-bus instruction (datasheet pp. 114, pdf pp. 114)
-            prog.register_emitted(InstrType::YBus);
-            return Ok(());
+            separator(lexer, relaxed)?;
+            expect_fixed_dest(&T::Alu, &T::A, lexer)?;
+            return emit_bus_mov(&T::Alu, &T::A, prog);
+        }
+
+        // MOV ALL, [d] / MOV ALH, [d]
+        let source = token(lexer)?;
+        if matches!(source, T::All | T::Alh) {
+            lexer.next();
+            separator(lexer, relaxed)?;
+            return emit_d1_mov(&source, lexer, prog);
         }
 
         // Otherwise, we expect a memory address
         // take the token for now, we'll check it again later in emit_xbus_mov
-        let tok = token_pop(lexer)?;
-        expect(&T::Comma, lexer)?;
-
-        // MOV [s], X
-        if accept(&T::X, lexer)? {
-            emit_mov(&tok, MovDestination::X, prog)?;
-            return Ok(());
+        if !(source.is_number() || source.is_ident() || REGISTER_TOKENS.contains(&&source)) {
+            let got = match source {
+                T::Comma => "','".into(),
+                T::Newline => "end of line".into(),
+                _ => token_str(lexer)?,
+            };
+            return Err(eyre!("Syntax error: Expected a source operand, got {got}"));
         }
+        let tok = operand(lexer, prog)?;
+        separator(lexer, relaxed)?;
 
-        // MOV [s], P
-        if accept(&T::P, lexer)? {
-            emit_mov(&tok, MovDestination::P, prog)?;
-            return Ok(());
+        // MOV [s], X / MOV [s], P / MOV [s], Y
+        let dest = token(lexer)?;
+        if matches!(dest, T::X | T::P | T::Y) {
+            lexer.next();
+            return emit_bus_mov(&tok, &dest, prog);
         }
 
-        // MOV [s], Y
-        if accept(&T::Y, lexer)? {
-            emit_mov(&tok, MovDestination::Y, prog)?;
-            return Ok(());
+        // MOV SImm, [d]; a register source would be MOV [s], [d] instead
+        if tok.is_number() || tok.is_ident() {
+            return emit_mov_simm(&tok, lexer, prog);
         }
 
-        // MOV SImm, [d]
-        // FIXME now that I think about it, I think we're parsing this wrong, why are we looking at
-        // SIMM_DEST? we should be looking for numbers or labels
-        // specifically checking if tok is one of those, an ident or num
-        // or; well; we should be doing both to disambiguate this from MOV [s], [d] -> check if
-        // BOTH "tok" from before is a number or label, AND [d] is a valid MOV SImm destination
-        if SIMM_DEST.contains(&&token(lexer)?) {
-            emit_mov_simm(&tok, lexer, prog)?;
-            return Ok(());
+        // MOV [s], [d] over the D1 bus
+        if d1_source(&tok).is_some() {
+            return emit_d1_mov(&tok, lexer, prog);
         }
 
-        // TODO support MOV [s], [d]
-
         // otherwise, illegal
         Err(eyre!(
             "Syntax error: Illegal source for MOV instruction, got: {}",
@@ -421,184 +705,740 @@ fn mov(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_ey
     }
 }
 
-fn clr(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_eyre::Result<()> {
-    debug!("Parse CLR A");
-    expect(&T::Clr, lexer)?;
-    expect(&T::A, lexer)?;
-    prog.emit_bit(17);
-    prog.register_emitted(InstrType::YBus);
-    Ok(())
-}
-
-fn loop_cmd(
-    lexer: &mut Peekable<Lexer<ScuDspToken>>,
-    prog: &mut Program,
-) -> color_eyre::Result<()> {
-    debug!("Parse loop");
+/// Errors if an MVI immediate doesn't fit in its signed field of `bits` bits. Labels are checked
+/// against the field when they're resolved instead.
+pub(crate) fn check_mvi_imm(value: &Imm, bits: u32, form: &str) -> color_eyre::Result<()> {
+    let Imm::Value(value) = value else {
+        return Ok(());
+    };
 
-    if accept(&T::Btm, lexer)? {
-        prog.emit_bits(vec![31, 30, 29]);
-    } else if accept(&T::Lps, lexer)? {
-        prog.emit_bits(vec![31, 30, 29, 27]);
-    } else {
+    let signed = *value as i32;
+    let limit = 1 << (bits - 1);
+    if !(-limit..limit).contains(&signed) {
         return Err(eyre!(
-            "Syntax error: Could not parse loop (BTM/LPS) instruction near {}",
-            token_str(lexer)?
+            "Error: '{signed}' will not fit in signed {bits}-bit immediate value (in {form}); \
+            the range is {} to {}",
+            -limit,
+            limit - 1
         ));
     }
 
-    // this probably isn't necessary since we force a newline anyway below, but just in case
-    prog.register_emitted(InstrType::FlowControl);
+    Ok(())
+}
 
-    // manual pp. 91 (pdf pp. 107) seems to imply that END and LOOP type instructions are
-    // completely separate to the normal bundle. The normal bundle can contain ALU, {X,Y,D1}-bus
-    // control, but it seems that END and LOOP must be on their own. Hence, we expect a newline to
-    // be issued.
-    if !accept(&T::Newline, lexer)? {
+/// Returns the MVI destination select bits (29-26) for a register, as per the Load Immediate
+/// section of the SCU manual
+pub(crate) fn mvi_dest(dest: &ScuDspToken) -> Option<u32> {
+    match dest {
+        T::Mc0 => Some(0b0000),
+        T::Mc1 => Some(0b0001),
+        T::Mc2 => Some(0b0010),
+        T::Mc3 => Some(0b0011),
+        T::Rx => Some(0b0100),
+        T::Pl => Some(0b0101),
+        T::Ra0 => Some(0b0110),
+        T::Wa0 => Some(0b0111),
+        T::Lop => Some(0b1010),
+        // loading the program counter jumps, like JMP
+        T::Pc => Some(0b1100),
+        _ => None,
+    }
+}
+
+// MVI Imm, [d] and MVI Imm, [d], cond
+fn mvi(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    debug!("Parse MVI");
+    expect(&T::Mvi, lexer)?;
+    let value = imm(lexer, prog)?;
+    separator(lexer, relaxed)?;
+
+    let dest = operand(lexer, prog)?;
+    reject_bit_range(lexer)?;
+    let Some(dest_bits) = mvi_dest(&dest) else {
         return Err(eyre!(
-            "Syntax error: Expected a newline after LPS/BTM. \
-            These instructions must be issued on their own, not as part of a bundle."
+            "Syntax error: Illegal MVI destination address, got: {}",
+            dest.as_ref()
         ));
+    };
+
+    if dest == T::Lop {
+        prog.mark_lop_loaded();
+    }
+
+    let word = 0_u32.set_bit(31) | (dest_bits << 26);
+    if accept(&T::Comma, lexer)? || (relaxed && !at_end_of_line(lexer)?) {
+        // conditional form, the condition eats into the immediate leaving 19 bits (signed)
+        let Some(cond) = condition(lexer)? else {
+            return Err(eyre!(
+                "Syntax error: Expected a condition code but got {}",
+                token_str(lexer)?
+            ));
+        };
+        check_mvi_imm(&value, 19, "MVI Imm, [d], cond")?;
+        prog.emit(word | (cond << 19));
+        emit_imm(value, 0x3_FFFF, 0x7_FFFF, prog)?;
+    } else {
+        // unconditional form, 25-bit signed immediate
+        check_mvi_imm(&value, 25, "MVI Imm, [d]")?;
+        prog.emit(word);
+        emit_imm(value, 0xFF_FFFF, 0x1FF_FFFF, prog)?;
     }
+    prog.register_emitted(InstrType::LoadImm);
 
     Ok(())
 }
 
-fn end(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_eyre::Result<()> {
-    debug!("Parse end");
+/// Returns the RAM select bits for a DMA transfer to or from data RAM
+fn dma_ram(ram: &T) -> Option<u32> {
+    match ram {
+        T::M0 => Some(0b000),
+        T::M1 => Some(0b001),
+        T::M2 => Some(0b010),
+        T::M3 => Some(0b011),
+        _ => None,
+    }
+}
 
-    if accept(&T::End, lexer)? {
-        prog.emit_bits(vec![31, 30, 29, 28]);
-    } else if accept(&T::Endi, lexer)? {
-        prog.emit_bits(vec![31, 30, 29, 28, 27]);
+// DMA D0, [RAM], count and DMA [RAM], D0, count, as well as the DMAH (hold) variants
+fn dma(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    debug!("Parse DMA");
+
+    let mut word = 0_u32.set_bit(31).set_bit(30);
+    if accept(&T::Dmah, lexer)? {
+        // hold the D0 address rather than incrementing it after the transfer
+        word = word.set_bit(14);
+    } else {
+        expect(&T::Dma, lexer)?;
+    }
+
+    let from = operand(lexer, prog)?;
+    separator(lexer, relaxed)?;
+    let to = operand(lexer, prog)?;
+    separator(lexer, relaxed)?;
+
+    let ram_bits = if from == T::D0 {
+        // D0 -> DSP, this is the only direction that can write to program RAM
+        if to == T::Prg {
+            Some(0b100)
+        } else {
+            dma_ram(&to)
+        }
+    } else if to == T::D0 {
+        // DSP -> D0
+        word = word.set_bit(12);
+        dma_ram(&from)
     } else {
+        None
+    };
+    let Some(ram_bits) = ram_bits else {
         return Err(eyre!(
-            "Syntax error: Could not parse END instruction near {}",
-            token_str(lexer)?
+            "Syntax error: Illegal DMA transfer from {} to {}; one side must be D0 and the other \
+            M0-M3 (or PRG, when transferring from D0)",
+            from.as_ref(),
+            to.as_ref()
         ));
+    };
+    word |= ram_bits << 8;
+
+    // the transfer count is either an 8-bit immediate or read from data RAM
+    let count_src = match token(lexer)? {
+        T::M0 => Some(0b000),
+        T::M1 => Some(0b001),
+        T::M2 => Some(0b010),
+        T::M3 => Some(0b011),
+        T::Mc0 => Some(0b100),
+        T::Mc1 => Some(0b101),
+        T::Mc2 => Some(0b110),
+        T::Mc3 => Some(0b111),
+        _ => None,
+    };
+    if let Some(count_src) = count_src {
+        lexer.next();
+        prog.emit(word.set_bit(13) | count_src);
+    } else {
+        let count = num(lexer)?;
+        if count > u8::MAX as u32 {
+            return Err(eyre!(
+                "Error: DMA transfer count '{count}' is out of range (max 255)"
+            ));
+        }
+        prog.emit(word | count);
     }
+    prog.register_emitted(InstrType::Dma);
 
-    // this probably isn't necessary since we force a newline anyway below, but just in case
-    prog.register_emitted(InstrType::FlowControl);
+    Ok(())
+}
 
-    // manual pp. 91 (pdf pp. 107) seems to imply that END and LOOP type instructions are
-    // completely separate to the normal bundle. The normal bundle can contain ALU, {X,Y,D1}-bus
-    // control, but it seems that END and LOOP must be on their own. Hence, we expect a newline to
-    // be issued.
-    if !accept(&T::Newline, lexer)? {
+// DW value, value, ...
+fn dw(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse DW");
+    expect(&T::Dw, lexer)?;
+    if prog.is_emitting() {
         return Err(eyre!(
-            "Syntax error: Expected a newline after END/ENDI. \
-            These instructions must be issued on their own, not as part of a bundle."
+            "Syntax error: DW cannot be combined with instructions in a bundle"
         ));
     }
 
+    loop {
+        match imm(lexer, prog)? {
+            Imm::Value(value) => prog.emit_data(value)?,
+            Imm::Label(label) => prog.emit_data_label(label)?,
+        }
+        if !accept(&T::Comma, lexer)? {
+            break;
+        }
+    }
+
     Ok(())
 }
 
-fn instr(lexer: &mut Peekable<Lexer<ScuDspToken>>, prog: &mut Program) -> color_eyre::Result<()> {
-    let tok = token(lexer)?;
-    debug!("Parse instr near {}", tok.as_ref());
-    if ALU_TOKENS.contains(&&tok) {
+/// Parses a value in an IF expression: a number, a define, or a label that's already been
+/// declared
+fn expr_value(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<u32> {
+    match imm(lexer, prog)? {
+        Imm::Value(value) => Ok(value),
+        Imm::Label(label) => match prog.labels().get(&label) {
+            Some(addr) => Ok(*addr),
+            None => Err(eyre!(
+                "Error: '{label}' must be declared before it can be used in an IF expression"
+            )),
+        },
+    }
+}
+
+// IFDEF name, true if name has been declared as a define (with EQU or =)
+fn ifdef(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse IFDEF");
+    expect(&T::Ifdef, lexer)?;
+
+    match token_pop(lexer)? {
+        T::Ident(name) => {
+            let condition = prog.is_define(&name);
+            prog.push_conditional(condition);
+            Ok(())
+        }
+        tok => Err(eyre!(
+            "Syntax error: Expected a define name after IFDEF but got {}",
+            tok.as_ref()
+        )),
+    }
+}
+
+// IF value and IF value op value, where op is one of ==, !=, < or >
+fn if_cond(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse IF");
+    expect(&T::If, lexer)?;
+
+    let lhs = expr_value(lexer, prog)?;
+    let condition = if at_end_of_line(lexer)? {
+        lhs != 0
+    } else {
+        let op = token_pop(lexer)?;
+        let rhs = expr_value(lexer, prog)?;
+        match op {
+            T::EqualsEquals => lhs == rhs,
+            T::NotEquals => lhs != rhs,
+            T::Less => lhs < rhs,
+            T::Greater => lhs > rhs,
+            _ => {
+                return Err(eyre!(
+                    "Syntax error: Expected a comparison (==, !=, < or >) in IF but got {}",
+                    op.as_ref()
+                ));
+            }
+        }
+    };
+    prog.push_conditional(condition);
+
+    Ok(())
+}
+
+// JMP target and JMP cond, target
+fn jmp(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    debug!("Parse JMP");
+    ensure_alone(&T::Jmp, prog)?;
+    expect(&T::Jmp, lexer)?;
+
+    let cond = condition(lexer)?;
+    if cond.is_some() {
+        separator(lexer, relaxed)?;
+    }
+
+    let target = imm(lexer, prog)?;
+    if let Imm::Value(target) = target
+        && target > u8::MAX as u32
+    {
+        return Err(eyre!(
+            "Error: '{target}' is out of range for a JMP target (program RAM is 256 words)"
+        ));
+    }
+
+    prog.emit(0_u32.set_bit(31).set_bit(30).set_bit(28) | (cond.unwrap_or(0) << 19));
+    emit_imm(target, 0xFF, 0xFF, prog)?;
+    prog.register_emitted(InstrType::FlowControl);
+
+    Ok(())
+}
+
+fn clr(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse CLR A");
+    expect(&T::Clr, lexer)?;
+    expect(&T::A, lexer)?;
+    emit_clr_a(prog);
+    Ok(())
+}
+
+pub(crate) fn emit_clr_a(prog: &mut Program) {
+    prog.emit_bit(17);
+    prog.register_emitted(InstrType::YBus);
+    prog.register_destination(MovDestination::A);
+}
+
+/// Errors if the bundle already has instructions in it, for instructions that take up the whole
+/// word. Checked before emitting so the bits never get mixed into the bundle.
+pub(crate) fn ensure_alone(mnemonic: &ScuDspToken, prog: &Program) -> color_eyre::Result<()> {
+    if prog.current_bundle_instructions() > 0 {
+        return Err(eyre!(
+            "Illegal program: {} cannot be combined with other instructions in a bundle",
+            mnemonic.as_ref().to_uppercase()
+        ));
+    }
+
+    Ok(())
+}
+
+fn loop_cmd(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse loop");
+
+    let mnemonic = token(lexer)?;
+    ensure_alone(&mnemonic, prog)?;
+    if accept(&T::Btm, lexer)? {
+        prog.emit_bits(vec![31, 30, 29]);
+    } else if accept(&T::Lps, lexer)? {
+        prog.emit_bits(vec![31, 30, 29, 27]);
+    } else {
+        return Err(eyre!(
+            "Syntax error: Could not parse loop (BTM/LPS) instruction near {}",
+            token_str(lexer)?
+        ));
+    }
+
+    // this probably isn't necessary since we force a newline anyway below, but just in case
+    prog.register_emitted(InstrType::FlowControl);
+
+    // the loop count comes from LOP, so it almost certainly should have been loaded beforehand
+    if !prog.take_lop_loaded() {
+        prog.add_warning(format!(
+            "{} loop entered without loading the loop counter first (e.g. MVI #n, LOP)",
+            mnemonic.as_ref().to_uppercase()
+        ));
+    }
+
+    // manual pp. 91 (pdf pp. 107) seems to imply that END and LOOP type instructions are
+    // completely separate to the normal bundle. The normal bundle can contain ALU, {X,Y,D1}-bus
+    // control, but it seems that END and LOOP must be on their own. Hence, we expect a newline to
+    // be issued.
+    if !at_end_of_line(lexer)? {
+        return Err(eyre!(
+            "Syntax error: Expected a newline after LPS/BTM. \
+            These instructions must be issued on their own, not as part of a bundle."
+        ));
+    }
+
+    Ok(())
+}
+
+fn end(lexer: &mut Tokens, prog: &mut Program) -> color_eyre::Result<()> {
+    debug!("Parse end");
+
+    ensure_alone(&token(lexer)?, prog)?;
+    if accept(&T::End, lexer)? {
+        prog.emit_bits(vec![31, 30, 29, 28]);
+    } else if accept(&T::Endi, lexer)? {
+        prog.emit_bits(vec![31, 30, 29, 28, 27]);
+    } else {
+        return Err(eyre!(
+            "Syntax error: Could not parse END instruction near {}",
+            token_str(lexer)?
+        ));
+    }
+
+    // this probably isn't necessary since we force a newline anyway below, but just in case
+    prog.register_emitted(InstrType::FlowControl);
+
+    // manual pp. 91 (pdf pp. 107) seems to imply that END and LOOP type instructions are
+    // completely separate to the normal bundle. The normal bundle can contain ALU, {X,Y,D1}-bus
+    // control, but it seems that END and LOOP must be on their own. Hence, we expect a newline to
+    // be issued.
+    if !at_end_of_line(lexer)? {
+        return Err(eyre!(
+            "Syntax error: Expected a newline after END/ENDI. \
+            These instructions must be issued on their own, not as part of a bundle."
+        ));
+    }
+
+    Ok(())
+}
+
+fn instr(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    let tok = token(lexer)?;
+    debug!("Parse instr near {}", tok.as_ref());
+    if ALU_TOKENS.contains(&&tok) {
         // NOTE: This will also handle NOP
         alu(lexer, prog)?;
     } else if tok == T::Mov {
-        mov(lexer, prog)?;
+        mov(lexer, prog, relaxed)?;
     } else if tok == T::Clr {
         clr(lexer, prog)?;
+    } else if tok == T::Mvi {
+        mvi(lexer, prog, relaxed)?;
+    } else if tok == T::Jmp {
+        jmp(lexer, prog, relaxed)?;
+    } else if DMA_TOKENS.contains(&&tok) {
+        dma(lexer, prog, relaxed)?;
     } else if LOOP_TOKENS.contains(&&tok) {
         loop_cmd(lexer, prog)?;
     } else if END_TOKENS.contains(&&tok) {
         end(lexer, prog)?;
     } else {
-        return Err(eyre!(
-            "Syntax error: Could not parse instruction near {}",
-            token_str(lexer)?
-        ));
+        return Err(unknown_instr(lexer)?);
     }
 
     Ok(())
 }
 
-pub fn document(
-    lexer: &mut Peekable<Lexer<ScuDspToken>>,
+/// Builds the error for a token that should have been an instruction, suggesting the closest
+/// mnemonic if it looks like a typo
+fn unknown_instr(lexer: &mut Tokens) -> color_eyre::Result<color_eyre::Report> {
+    let near = token_str(lexer)?;
+    let suggestion = match token(lexer)? {
+        T::Ident(name) => closest_mnemonic(&name),
+        _ => None,
+    };
+
+    Ok(match suggestion {
+        Some(mnemonic) => eyre!(
+            "Syntax error: Could not parse instruction near {near}; did you mean `{mnemonic}`?"
+        ),
+        None => eyre!("Syntax error: Could not parse instruction near {near}"),
+    })
+}
+
+/// Returns the instruction mnemonic closest to `word`, if it's within two edits (and isn't a
+/// complete rewrite of a short word)
+fn closest_mnemonic(word: &str) -> Option<String> {
+    let word = word.to_uppercase();
+    INSTR_TOKENS
+        .iter()
+        .map(|tok| tok.as_ref().to_uppercase())
+        .map(|mnemonic| (levenshtein(&word, &mnemonic), mnemonic))
+        .filter(|(distance, _)| *distance <= 2 && *distance < word.chars().count())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, mnemonic)| mnemonic)
+}
+
+/// Number of single character insertions, deletions and substitutions to turn `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from the prefix of `a` seen so far to each prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+pub fn document(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    statements(lexer, prog, relaxed)?;
+
+    // now that every label has been seen, fill in any forward references
+    prog.finalise()
+}
+
+/// An error found by [`document_recovering`], along with where it was found
+#[derive(Debug)]
+pub struct LineError {
+    /// Line the error occurred on, starting at 0
+    pub line: u32,
+
+    /// Columns (starting at 0) of the token the error points at, if it's about a single token
+    pub column: Option<Range<u32>>,
+
+    pub error: color_eyre::Report,
+}
+
+impl LineError {
+    fn new(prog: &Program, error: color_eyre::Report) -> Self {
+        Self {
+            line: prog.line,
+            column: None,
+            error,
+        }
+    }
+}
+
+/// Like [`document`], but carries on past errors rather than stopping at the first one. The rest
+/// of the line an error occurs on is skipped. Returns every error in the order it was found.
+pub fn document_recovering(
+    lexer: &mut Tokens,
     prog: &mut Program,
     relaxed: bool,
-) -> color_eyre::Result<()> {
-    while lexer.peek().is_some() {
-        let tok = token(lexer)?;
-        debug!("TOK: {}", tok.as_ref());
-
-        if tok == T::Newline {
-            // finalise the current bundle
-            prog.flush()?;
-            // skip newline
+) -> Vec<LineError> {
+    let mut errors = Vec::new();
+
+    while let Some(tok) = lexer.peek() {
+        if matches!(tok, Ok(T::Newline)) {
+            if let Err(error) = prog.flush() {
+                errors.push(LineError::new(prog, error));
+                prog.discard();
+            }
             lexer.next();
             prog.line += 1;
             continue;
         }
 
-        // first try match a define
-        // if a line starts with an ident, we assume they're trying to write a define
-        if tok.is_ident() {
-            lexer.next();
-
-            // in relaxed mode, they might have intended it to be a label
-            if relaxed && token(lexer)? != T::Equals {
-                // TODO we should actually check this is valid to do right
-                debug!("Trying to recover ident -> label in relaxed mode");
-                match tok {
-                    T::Ident(lab) => {
-                        prog.add_label(lab);
-                    }
-                    _ => {
-                        panic!("Internal error: Should have been an ident!");
-                    }
-                }
-                continue;
+        if let Err(error) = statement(lexer, prog, relaxed) {
+            errors.push(LineError {
+                column: lexer.marked_columns(),
+                ..LineError::new(prog, error)
+            });
+            prog.discard();
+            // skip the rest of the line, the newline itself is handled above
+            while lexer
+                .peek()
+                .is_some_and(|tok| !matches!(tok, Ok(T::Newline)))
+            {
+                lexer.next();
             }
+        }
+    }
 
-            // normal non-relaxed mode
-            // should be in the form X = Y; check eq
-            expect(&T::Equals, lexer)?;
-            let num = num(lexer)?;
+    if let Err(error) = prog.flush() {
+        errors.push(LineError::new(prog, error));
+        prog.discard();
+    }
+    if let Err(error) = prog.finalise() {
+        errors.push(LineError::new(prog, error));
+    }
+
+    errors
+}
+
+/// Parses a single statement (a define, label, directive or instruction), adding any instruction
+/// to the current bundle without flushing it
+fn statement(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    let tok = token(lexer)?;
+    lexer.mark();
+
+    // inside a false IF block, skip everything but keep track of nesting so we find the right
+    // ENDIF
+    if prog.is_skipping() {
+        lexer.next();
+        match tok {
+            T::If | T::Ifdef => prog.push_conditional(false),
+            T::Endif => prog.pop_conditional()?,
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    // conditional assembly
+    if tok == T::If {
+        return if_cond(lexer, prog);
+    }
+    if tok == T::Ifdef {
+        return ifdef(lexer, prog);
+    }
+    if tok == T::Endif {
+        lexer.next();
+        return prog.pop_conditional();
+    }
+
+    // a backslash continues the bundle onto the next line. comments are skipped by the lexer,
+    // so one may sit between the backslash and the newline, and comment-only lines after it
+    // don't end the bundle either.
+    if tok == T::Backslash {
+        lexer.next();
+        if !accept(&T::Newline, lexer)? {
+            return Err(eyre!(
+                "Syntax error: Expected a newline after line continuation '\\' but got {}",
+                token_str(lexer)?
+            ));
+        }
+        prog.line += 1;
+        while accept(&T::Newline, lexer)? {
+            prog.line += 1;
+        }
+        return Ok(());
+    }
+
+    // first try match a define
+    // if a line starts with an ident, we assume they're trying to write a define
+    if tok.is_ident() {
+        lexer.next();
+
+        // in relaxed mode, they might have intended it to be a label
+        let next = token(lexer)?;
+        if relaxed && next != T::Equals && next != T::Equ {
+            // TODO we should actually check this is valid to do right
+            debug!("Trying to recover ident -> label in relaxed mode");
             match tok {
                 T::Ident(lab) => {
-                    prog.add_define(lab, num)?;
+                    prog.add_label(lab);
                 }
                 _ => {
                     panic!("Internal error: Should have been an ident!");
                 }
             }
-            continue;
+            return Ok(());
         }
 
-        // then try a label
-        if tok.is_label() {
-            match token(lexer)? {
-                T::Label(lab) => {
-                    prog.add_label(lab);
-                }
-                _ => {
-                    // we already checked above tok.is_label(), so this should never happen
-                    panic!("Internal error: Should have been a label!");
-                }
+        // a misspelt instruction would otherwise be reported as a malformed define
+        if next != T::Equals
+            && next != T::Equ
+            && let T::Ident(name) = &tok
+            && let Some(mnemonic) = closest_mnemonic(name)
+        {
+            return Err(eyre!(
+                "Syntax error: Could not parse instruction near {} '{name}'; did you mean \
+                `{mnemonic}`?",
+                tok.as_ref()
+            ));
+        }
+
+        // normal non-relaxed mode
+        // should be in the form X = Y or X EQU Y, where Y is a number, a register or another define
+        if !accept(&T::Equ, lexer)? {
+            expect(&T::Equals, lexer)?;
+        }
+        let value = match token(lexer)? {
+            T::Ident(other) => {
+                lexer.next();
+                Define::Alias(other)
+            }
+            other if REGISTER_TOKENS.contains(&&other) => {
+                lexer.next();
+                Define::Token(other)
+            }
+            _ => Define::Value(num(lexer)?),
+        };
+        match tok {
+            T::Ident(lab) => {
+                prog.add_define(lab, value)?;
+            }
+            _ => {
+                panic!("Internal error: Should have been an ident!");
             }
-            continue;
         }
+        return Ok(());
+    }
 
-        // org directive
-        if tok == T::Org {
-            lexer.next();
-            let addr = num(lexer)?;
-            // TODO handle this
+    // then try a label
+    if tok.is_label() {
+        match token_pop(lexer)? {
+            T::Label(lab) => {
+                prog.add_label(lab);
+            }
+            _ => {
+                // we already checked above tok.is_label(), so this should never happen
+                panic!("Internal error: Should have been a label!");
+            }
+        }
+        return Ok(());
+    }
+
+    // org directive
+    if tok == T::Org {
+        lexer.next();
+        let addr = num(lexer)?;
+        prog.flush()?;
+        prog.org(addr)?;
+        return Ok(());
+    }
+
+    // section directives
+    if tok == T::Code || tok == T::Data {
+        lexer.next();
+        // a section switch ends the current bundle
+        prog.flush()?;
+        prog.set_section(if tok == T::Code {
+            Section::Code
+        } else {
+            Section::Data
+        });
+        return Ok(());
+    }
+
+    // .space n (or .skip n), reserves n words
+    if tok == T::Space {
+        lexer.next();
+        let count = num(lexer)?;
+        prog.flush()?;
+        prog.space(count)?;
+        return Ok(());
+    }
+
+    // .maxbundle n, caps the instructions in each bundle for the rest of the file
+    if tok == T::Maxbundle {
+        lexer.next();
+        let total = num(lexer)?;
+        prog.set_max_bundle(total)?;
+        return Ok(());
+    }
+
+    // data words
+    if tok == T::Dw {
+        dw(lexer, prog)?;
+        return Ok(());
+    }
+
+    // now look for instructions
+    if INSTR_TOKENS.contains(&&tok) {
+        prog.check_org()?;
+        prog.check_one_instr_per_line(FLOW_CONTROL_TOKENS.contains(&&tok))?;
+        if prog.section() == Section::Data {
+            return Err(eyre!(
+                "Error: Instructions can't be placed in the .data section; switch back with .code \
+                first"
+            ));
         }
+        // begin a new bundle if we haven't already
+        prog.begin_if_not_begun();
+        instr(lexer, prog, relaxed)?;
+        return Ok(());
+    }
+
+    // anything else can't start a statement
+    Err(eyre!(
+        "Syntax error: Could not parse statement near {}",
+        token_str(lexer)?
+    ))
+}
+
+/// Parses statements until the end of the input, leaving forward label references unresolved
+fn statements(lexer: &mut Tokens, prog: &mut Program, relaxed: bool) -> color_eyre::Result<()> {
+    while lexer.peek().is_some() {
+        let tok = token(lexer)?;
+        debug!("TOK: {}", tok.as_ref());
 
-        // now look for instructions
-        if INSTR_TOKENS.contains(&&tok) {
-            // begin a new bundle if we haven't already
-            prog.begin_if_not_begun();
-            instr(lexer, prog)?;
+        if tok == T::Newline {
+            // finalise the current bundle
+            prog.flush()?;
+            // skip newline
+            lexer.next();
+            prog.line += 1;
+            continue;
         }
+
+        statement(lexer, prog, relaxed).inspect_err(|_| prog.column = lexer.marked_columns())?;
     }
 
     // end of document, flush final instruction (if one exists)
@@ -607,13 +1447,64 @@ pub fn document(
     Ok(())
 }
 
+/// Assembles a single line of source into an existing program, returning the word it emitted (if
+/// any). Labels and defines from earlier lines remain visible, which is what the REPL relies on.
+/// References to labels that haven't been declared yet are left unresolved.
+pub fn assemble_line(
+    line: &str,
+    prog: &mut Program,
+    relaxed: bool,
+) -> color_eyre::Result<Option<u32>> {
+    let before = prog.words().len();
+    let source = format!("{line}\n");
+    let mut tokens = lex(source.as_str());
+
+    if let Err(error) = statements(&mut tokens, prog, relaxed) {
+        // don't let a half-parsed bundle leak into the next line
+        prog.discard();
+        return Err(error);
+    }
+
+    if prog.words().len() > before {
+        Ok(prog.words().last().copied())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Assembles a single bundle on its own and returns its instruction word
+pub fn assemble_bundle(source: &str) -> color_eyre::Result<u32> {
+    let mut prog = Program::default();
+    match assemble_line(source, &mut prog, false)? {
+        Some(word) => Ok(word),
+        None => Err(eyre!("Error: '{source}' does not contain any instructions")),
+    }
+}
+
+/// Checks whether a single line of source is a legal bundle under the given limits, without
+/// assembling it into a program. Labels referenced by the line don't need to exist.
+pub fn check_bundle(source_line: &str, limits: &BundleLimits) -> Result<(), AssembleError> {
+    let mut prog = Program::default();
+    prog.set_limits(*limits);
+
+    let mut lexer = lex(source_line);
+    while lexer
+        .peek()
+        .is_some_and(|tok| !matches!(tok, Ok(T::Newline)))
+    {
+        statement(&mut lexer, &mut prog, false)
+            .map_err(|error| AssembleError::Syntax(error.to_string()))?;
+    }
+
+    prog.validate_bundle()
+}
+
 // TODO move these to another file; see how some bigger rust projects do it?
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    use crate::tokeniser::lex;
+    use crate::emitter::PadPolicy;
 
     fn expect_failing_program(doc: &'static str, msg: &'static str) {
         let _ = env_logger::try_init();
@@ -626,11 +1517,17 @@ mod tests {
     }
 
     fn validate_program(doc: &'static str) -> color_eyre::Result<()> {
+        assemble_program(doc)?;
+
+        Ok(())
+    }
+
+    fn assemble_program(doc: &'static str) -> color_eyre::Result<Program> {
         let mut tokens = lex(doc);
         let mut prog = Program::default();
         document(&mut tokens, &mut prog, false)?;
 
-        Ok(())
+        Ok(prog)
     }
 
     #[test]
@@ -639,7 +1536,7 @@ mod tests {
         let mut tokens = lex(document);
         let mut prog = Program::default();
         prog.begin();
-        instr(&mut tokens, &mut prog)?;
+        instr(&mut tokens, &mut prog, false)?;
         prog.flush()?;
 
         Ok(())
@@ -651,7 +1548,7 @@ mod tests {
         let mut tokens = lex(document);
         let mut prog = Program::default();
         prog.begin();
-        instr(&mut tokens, &mut prog)?;
+        instr(&mut tokens, &mut prog, false)?;
         prog.flush()?;
 
         Ok(())
@@ -696,7 +1593,15 @@ mod tests {
     }
 
     #[test]
-    fn test_btm_must_be_on_its_own() {
+    fn test_end_flushes_its_own_bundle() -> color_eyre::Result<()> {
+        let prog = assemble_program("CLR A\nENDI\nCLR A")?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0xF800_0000, 0x0002_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btm_must_be_on_its_own() {
         expect_failing_program(
             r#"
             CLR A
@@ -767,4 +1672,1212 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_mvi_lop() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            r#"
+            MVI #3, LOP
+            LPS
+            CLR A
+        "#,
+        )?;
+
+        // 10 | LOP = 1010 | imm 3
+        assert_eq!(prog.words()[0], 0xA800_0003);
+        assert!(prog.warnings().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_without_lop_warns() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            r#"
+            CLR A
+            LPS
+            CLR A
+        "#,
+        )?;
+
+        assert_eq!(prog.warnings().len(), 1);
+        assert!(
+            prog.warnings()[0]
+                .message
+                .contains("without loading the loop counter")
+        );
+        assert_eq!(prog.warnings()[0].line, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mvi_alone_in_bundle() {
+        expect_failing_program("MVI #3, LOP  CLR A", "MVI cannot be combined");
+    }
+
+    #[test]
+    fn test_mvi_illegal_destination() {
+        expect_failing_program("MVI #1, M0", "Illegal MVI destination address");
+    }
+
+    #[test]
+    fn test_mvi_conditional() -> color_eyre::Result<()> {
+        let prog = assemble_program("MVI #5, MC0, NZ")?;
+        // 10 | MC0 = 0000 | NZ = 1000001 | imm 5
+        assert_eq!(prog.words()[0], 0x8208_0005);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mvi_encoding() -> color_eyre::Result<()> {
+        let prog = assemble_program("MVI #-2, MC1\nMVI $10, PC\nMVI #7, RX, Z\n")?;
+        // 10 | MC1 = 0001 | 25-bit imm -2
+        assert_eq!(prog.words()[0], 0x85FF_FFFE);
+        // 10 | PC = 1100 | imm $10
+        assert_eq!(prog.words()[1], 0xB000_0010);
+        // 10 | RX = 0100 | Z = 1100001 | imm 7
+        assert_eq!(prog.words()[2], 0x9308_0007);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_decimals() -> color_eyre::Result<()> {
+        // negative values are two's complement, so bounds checks can treat them as signed
+        assert_eq!(parse_num("#-5")?, -5_i32 as u32);
+        assert_eq!(parse_num("-5")?, -5_i32 as u32);
+        assert_eq!(parse_num("#-2147483648")?, i32::MIN as u32);
+        assert!(parse_num("#-2147483649").is_err());
+
+        let prog = assemble_program("MOV #-5, RX\nMOV -5, RX\nMVI -5, MC0\n")?;
+        assert_eq!(prog.words(), [0x0000_14FB, 0x0000_14FB, 0x81FF_FFFB]);
+        expect_failing_program("MOV -129, RX\n", "'-129' will not fit in signed 8-bit");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mvi_range() -> color_eyre::Result<()> {
+        // 25 bits signed, or 19 bits when conditional
+        validate_program("MVI #16777215, MC0\nMVI #-16777216, MC0\n")?;
+        validate_program("MVI #262143, MC0, Z\nMVI #-262144, MC0, Z\n")?;
+
+        expect_failing_program(
+            "MVI #16777216, MC0\n",
+            "Error: '16777216' will not fit in signed 25-bit immediate value (in MVI Imm, [d]); \
+            the range is -16777216 to 16777215",
+        );
+        expect_failing_program("MVI #-16777217, MC0\n", "'-16777217' will not fit");
+        expect_failing_program(
+            "MVI #262144, MC0, Z\n",
+            "'262144' will not fit in signed 19-bit immediate value (in MVI Imm, [d], cond)",
+        );
+        expect_failing_program("BIG EQU $1000000\nMVI BIG, LOP\n", "will not fit");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_conditions_distinct() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            r#"
+            JMP Z, $10
+            JMP ZS, $10
+            JMP NZ, $10
+            JMP NZS, $10
+        "#,
+        )?;
+
+        let words = prog.words();
+        assert_eq!(words[0], 0xD308_0010);
+        assert_eq!(words[1], 0xD318_0010);
+        assert_eq!(words[2], 0xD208_0010);
+        assert_eq!(words[3], 0xD218_0010);
+
+        // and the table itself must not contain any duplicate encodings
+        for (i, (_, a)) in CONDITIONS.iter().enumerate() {
+            for (_, b) in &CONDITIONS[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_load_rejected() {
+        expect_failing_program("MVI $3, CT0[0:3]", "Partial loads not supported");
+        expect_failing_program("MVI $3, MC0[0:3]", "Partial loads not supported");
+        expect_failing_program("MOV $3, CT0[0:3]", "Partial loads not supported");
+    }
+
+    #[test]
+    fn test_mvi_forward_label() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            r#"
+            MVI table, RA0
+            MVI table, WA0, Z
+            CLR A
+            table:
+            CLR A
+        "#,
+        )?;
+
+        // 10 | RA0 = 0110 | address 3
+        assert_eq!(prog.words()[0], 0x9800_0003);
+        // 10 | WA0 = 0111 | Z | address 3
+        assert_eq!(prog.words()[1], 0x9F08_0003);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undeclared_label() {
+        expect_failing_program(
+            "MVI nowhere, RA0",
+            "'nowhere' is not a declared define or label",
+        );
+    }
+
+    #[test]
+    fn test_leading_blank_lines_counted() {
+        let mut tokens = lex("\u{FEFF}\n   \n\n\t; comment\n  AD2 OR\n");
+        let mut prog = Program::default();
+        assert!(document(&mut tokens, &mut prog, false).is_err());
+        assert_eq!(prog.line, 4);
+    }
+
+    #[test]
+    fn test_continuation_with_comment() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            r#"
+            MOV M0,X  \ ; more coming
+            ; the bundle carries on past this comment
+            MOV M1,Y  \
+            CLR A
+            MOV MUL,P
+        "#,
+        )?;
+        assert_eq!(prog.words(), &[0x020A_4000, 0x0100_0000]);
+        assert_eq!(prog.line, 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_continuation_needs_newline() {
+        expect_failing_program(
+            "MOV M0,X \\ CLR A",
+            "Expected a newline after line continuation",
+        );
+    }
+
+    #[test]
+    fn test_define_alias() -> color_eyre::Result<()> {
+        let prog = assemble_program("COUNT EQU LIMIT\nLIMIT = 5\nMVI COUNT, LOP\n")?;
+        assert_eq!(prog.words()[0], 0xA800_0005);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_equ_immediate() -> color_eyre::Result<()> {
+        let prog = assemble_program("COUNT EQU 5\nMVI COUNT, LOP\nMOV COUNT, MC0\n")?;
+        assert_eq!(prog.words(), &[0xA800_0005, 0x0000_1005]);
+
+        expect_failing_program("COUNT EQU 5\nCOUNT EQU 6\n", "already been declared");
+        expect_failing_program(
+            "MVI COUNT, LOP\n",
+            "Symbol 'COUNT' is not a declared define or label",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_define_cycle() {
+        expect_failing_program(
+            "FOO EQU BAR\nBAR EQU FOO\nMVI FOO, MC0\n",
+            "Definition cycle: FOO -> BAR -> FOO",
+        );
+        // even if nothing uses them
+        expect_failing_program(
+            "ONE EQU TWO\nTWO EQU THREE\nTHREE EQU TWO\n",
+            "Definition cycle: ONE -> TWO -> THREE -> TWO",
+        );
+    }
+
+    /// (source, expected word) pairs, checked against the instruction formats in the SCU manual
+    const KNOWN_ENCODINGS: &[(&str, u32)] = &[
+        // ALU control, manual pp. 91 (pdf pp. 107) for the operation command layout
+        ("NOP", 0x0000_0000),
+        ("AND", 0x0400_0000),
+        ("OR", 0x0800_0000),
+        ("XOR", 0x0C00_0000),
+        ("ADD", 0x1000_0000),
+        ("SUB", 0x1400_0000),
+        ("AD2", 0x1800_0000),
+        ("SR", 0x2000_0000),
+        ("RR", 0x2400_0000),
+        ("SL", 0x2800_0000),
+        ("RL", 0x2C00_0000),
+        ("RL8", 0x3C00_0000),
+        // X-bus control, manual pp. 108 (pdf pp. 124)
+        ("MOV MUL, P", 0x0100_0000),
+        ("MOV MC1, X", 0x0250_0000),
+        // MOV [s], P, manual pp. 109 (pdf pp. 125)
+        ("MOV M3, P", 0x01B0_0000),
+        ("MOV MC2, P", 0x01E0_0000),
+        // Y-bus control, manual pp. 114
+        ("MOV ALU, A", 0x0004_0000),
+        ("CLR A", 0x0002_0000),
+        ("MOV M2, Y", 0x0008_8000),
+        ("MOV MC3, Y", 0x0009_C000),
+        // a full bundle, as in the manual's example program
+        (
+            "AD2  MOV MC2,X  MOV MUL,P  MOV MC3,Y  MOV ALU,A",
+            0x1B6D_C000,
+        ),
+        // load immediate
+        ("MVI #3, LOP", 0xA800_0003),
+        ("MVI #5, MC0, NZ", 0x8208_0005),
+        // jump
+        ("JMP $10", 0xD000_0010),
+        ("JMP T0, $20", 0xD340_0020),
+        // loop
+        ("BTM", 0xE000_0000),
+        ("LPS", 0xE800_0000),
+        // end
+        ("END", 0xF000_0000),
+        ("ENDI", 0xF800_0000),
+    ];
+
+    /// What a fixture should assemble to
+    enum Expect {
+        /// Assembles successfully into this many words
+        Words(usize),
+
+        /// Fails with an error containing this text
+        Error(&'static str),
+    }
+
+    /// Whole programs and what they should assemble to. Add a line here to cover a new case.
+    const FIXTURES: &[(&str, Expect)] = &[
+        // every instruction form
+        ("NOP\n", Expect::Words(1)),
+        (
+            "AND\nOR\nXOR\nADD\nSUB\nAD2\nSR\nRR\nSL\nRL\nRL8\n",
+            Expect::Words(11),
+        ),
+        ("MOV MUL,P\nMOV M0,P\nMOV MC1,X\n", Expect::Words(3)),
+        ("MOV ALU,A\nCLR A\nMOV M2,Y\n", Expect::Words(3)),
+        (
+            "AD2  MOV MC2,X  MOV MUL,P  MOV MC3,Y  MOV ALU,A\n",
+            Expect::Words(1),
+        ),
+        ("MVI #3,LOP\nMVI #1,MC0,NZ\n", Expect::Words(2)),
+        (
+            "DMA D0,M0,#4\nDMAH M1,D0,MC2\nDMA D0,PRG,#8\n",
+            Expect::Words(3),
+        ),
+        (
+            "start:\nJMP start\nJMP NZ,start\nJMP $10\n",
+            Expect::Words(3),
+        ),
+        ("MVI #3,LOP\nLPS\nMVI #3,LOP\nBTM\n", Expect::Words(4)),
+        ("END\n", Expect::Words(1)),
+        ("ENDI\n", Expect::Words(1)),
+        // directives
+        ("COUNT EQU 3\nMVI COUNT,LOP\n", Expect::Words(1)),
+        ("ORG 0\nCLR A\n", Expect::Words(1)),
+        ("CLR A\n.data\nDW 1, 2, 3\n.code\nEND\n", Expect::Words(5)),
+        (
+            "ONE EQU 1\nIF ONE == 1\nCLR A\nENDIF\nIF ONE != 1\nAD2\nENDIF\n",
+            Expect::Words(1),
+        ),
+        ("", Expect::Words(0)),
+        ("; just a comment\n\n", Expect::Words(0)),
+        // errors
+        ("AND OR\n", Expect::Error("more than one ALU")),
+        ("ADD END\n", Expect::Error("END cannot be combined")),
+        (
+            "LPS CLR A\n",
+            Expect::Error("Expected a newline after LPS/BTM"),
+        ),
+        ("MVI #1,LOP  CLR A\n", Expect::Error("cannot be combined")),
+        ("JMP nowhere\n", Expect::Error("nowhere")),
+        ("MOV , X\n", Expect::Error("Expected a source operand")),
+        ("DMA M0,M1,#1\n", Expect::Error("Illegal DMA transfer")),
+        ("DMA D0,M0,#256\n", Expect::Error("out of range")),
+        ("IF 1 == 1\nCLR A\n", Expect::Error("not closed with ENDIF")),
+        ("ENDIF\n", Expect::Error("ENDIF without a matching IF")),
+        (
+            ".data\nCLR A\n",
+            Expect::Error("can't be placed in the .data section"),
+        ),
+        ("MVI %102,LOP\n", Expect::Error("not a valid binary digit")),
+    ];
+
+    #[test]
+    fn test_fixtures() {
+        for (source, expect) in FIXTURES {
+            let mut tokens = lex(source);
+            let mut prog = Program::default();
+            let result = document(&mut tokens, &mut prog, false);
+
+            match (expect, result) {
+                (Expect::Words(len), Ok(())) => assert_eq!(
+                    prog.words().len(),
+                    *len,
+                    "'{source}' assembled to the wrong number of words"
+                ),
+                (Expect::Words(_), Err(error)) => panic!("'{source}' failed: {error}"),
+                (Expect::Error(msg), Err(error)) => assert!(
+                    error.to_string().contains(msg),
+                    "'{source}' failed with '{error}', expected '{msg}'"
+                ),
+                (Expect::Error(msg), Ok(())) => {
+                    panic!("'{source}' assembled, but should have failed with '{msg}'")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_encodings() -> color_eyre::Result<()> {
+        for (source, expected) in KNOWN_ENCODINGS {
+            let word = assemble_bundle(source)?;
+            assert_eq!(
+                word, *expected,
+                "'{source}' encoded to {word:#010x}, expected {expected:#010x}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_bundle() {
+        let limits = BundleLimits::default();
+        assert_eq!(
+            check_bundle("AD2  MOV MC2,X  MOV MUL,P  MOV MC3,Y  MOV ALU,A", &limits),
+            Ok(())
+        );
+        assert_eq!(check_bundle("JMP later", &limits), Ok(()));
+        assert_eq!(
+            check_bundle("AD2 OR", &limits),
+            Err(AssembleError::TooMany {
+                instr_type: InstrType::Alu,
+                count: 2,
+                limit: 1
+            })
+        );
+        assert!(matches!(
+            check_bundle("MOV ???", &limits),
+            Err(AssembleError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_bundle_custom_limits() {
+        let limits = BundleLimits {
+            xbus: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_bundle("MOV MUL,P  MOV M0,X", &limits),
+            Err(AssembleError::TooMany {
+                instr_type: InstrType::XBus,
+                count: 2,
+                limit: 1
+            })
+        );
+        assert_eq!(
+            check_bundle("MOV MUL,P  MOV M0,X", &BundleLimits::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_register_alias() -> color_eyre::Result<()> {
+        let aliased = assemble_program("SRC EQU M2\nDEST = LOP\nMOV SRC, X\nMVI #3, DEST\n")?;
+        let plain = assemble_program("MOV M2, X\nMVI #3, LOP\n")?;
+        assert_eq!(aliased.words(), plain.words());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_alias_misuse() {
+        // a register where a number is needed
+        expect_failing_program(
+            "SRC EQU M2\nMVI SRC, LOP\n",
+            "'SRC' is a register alias (M2), but a number is required here",
+        );
+
+        // and a number where a register is needed
+        expect_failing_program(
+            "COUNT EQU 4\nMOV COUNT, X\n",
+            "'COUNT' is a numeric define, but a register is required here",
+        );
+        expect_failing_program(
+            "COUNT EQU 4\nMVI #1, COUNT\n",
+            "Illegal MVI destination address",
+        );
+    }
+
+    #[test]
+    fn test_sections() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            "CLR A\n\
+             .data\n\
+             table:\n\
+             DW 1, 2\n\
+             .code\n\
+             loop:\n\
+             MOV MUL, P\n\
+             JMP table\n\
+             .data\n\
+             more:\n\
+             DW 255\n",
+        )?;
+
+        // code first, then data
+        assert_eq!(
+            prog.words(),
+            &[0x0002_0000, 0x0100_0000, 0xD000_0003, 1, 2, 255]
+        );
+        assert_eq!(prog.labels()["loop"], 1);
+        assert_eq!(prog.labels()["table"], 3);
+        assert_eq!(prog.labels()["more"], 5);
+        assert_eq!(prog.label_section("table"), Some(Section::Data));
+        assert_eq!(prog.label_section("loop"), Some(Section::Code));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instruction_in_data_section() {
+        expect_failing_program(
+            ".data\nCLR A\n",
+            "Instructions can't be placed in the .data section",
+        );
+    }
+
+    #[test]
+    fn test_if_comparisons() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            "VERSION = 2\n\
+             IF 2 == 2\n\
+             CLR A\n\
+             ENDIF\n\
+             IF 1 > 2\n\
+             MOV MUL, P\n\
+             ENDIF\n\
+             IF VERSION != 1\n\
+             AD2\n\
+             ENDIF\n\
+             ENDI\n",
+        )?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0x1800_0000, 0xF800_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_value() -> color_eyre::Result<()> {
+        // a bare value is true unless it's zero, and a false block declares nothing
+        let prog =
+            assemble_program("IF 5\nCLR A\nENDIF\nIF 0\nskipped: AD2\nCOUNT EQU 1\nENDIF\nENDI\n")?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0xF800_0000]);
+        assert!(!prog.labels().contains_key("skipped"));
+        assert!(!prog.is_define("COUNT"));
+
+        expect_failing_program(
+            "IF 1\nCLR A\nIF 0\nENDIF\n",
+            "Syntax error: 1 IF block(s) not closed with ENDIF",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ifdef() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            "DEBUG EQU 1\nIFDEF DEBUG\nCLR A\nENDIF\nIFDEF RELEASE\nAD2\nENDIF\nENDI\n",
+        )?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0xF800_0000]);
+
+        expect_failing_program(
+            "IFDEF 1\nENDIF\n",
+            "Expected a define name after IFDEF but got Num",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_nested_in_false_block() -> color_eyre::Result<()> {
+        // the inner ENDIF mustn't close the outer block
+        let prog = assemble_program("IF 0\nIF 1\nCLR A\nENDIF\nAD2\nENDIF\nENDI\n")?;
+        assert_eq!(prog.words(), &[0xF800_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_label_comparison() -> color_eyre::Result<()> {
+        let prog = assemble_program("start:\nCLR A\nfinish:\nIF start < finish\nAD2\nENDIF\n")?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0x1800_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_before_end() -> color_eyre::Result<()> {
+        let prog = assemble_program("CLR A\ntarget: ENDI\ndone: END\n")?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0xF800_0000, 0xF000_0000]);
+        assert_eq!(prog.labels()["target"], 1);
+        assert_eq!(prog.labels()["done"], 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_org() -> color_eyre::Result<()> {
+        let prog = assemble_program("ORG $10\nstart: CLR A\n")?;
+        assert_eq!(prog.labels()["start"], 0x10);
+        assert_eq!(prog.words().len(), 0x11);
+        assert!(prog.words()[..0x10].iter().all(|&word| word == 0));
+        assert_eq!(prog.words()[0x10], 0x0002_0000);
+
+        // the gap is filled according to the pad policy
+        let mut prog = Program::default();
+        prog.set_pad_policy(PadPolicy::Trap);
+        document(&mut lex("CLR A\nORG 3\nEND\n"), &mut prog, false)?;
+        assert_eq!(
+            prog.words(),
+            &[0x0002_0000, 0xF800_0000, 0xF800_0000, 0xF000_0000]
+        );
+
+        expect_failing_program("CLR A\nCLR A\nORG 1\n", "ORG can't move backwards");
+        expect_failing_program("ORG 256\n", "outside the 256 words of program RAM");
+        expect_failing_program(".data\nORG 4\n", "only be used in the code section");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dw_labels() -> color_eyre::Result<()> {
+        // a backward, a forward and a data section reference
+        let source = "start: CLR A\nDW start, done, table\ndone: END\n.data\ntable: DW 7\n";
+        let prog = assemble_program(source)?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0, 4, 5, 0xF000_0000, 7][..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebase() -> color_eyre::Result<()> {
+        let source = "start: CLR A\nJMP start\nJMP done\ndone: END\n.data\ntable: DW start, done\n";
+        let mut prog = assemble_program(source)?;
+        prog.rebase(16)?;
+        assert_eq!(prog.labels()["start"], 16);
+        assert_eq!(prog.labels()["table"], 20);
+        assert_eq!(
+            prog.words(),
+            &[0x0002_0000, 0xD000_0010, 0xD000_0013, 0xF000_0000, 16, 19][..]
+        );
+
+        // back again, then too far either way, which leaves the program alone
+        prog.rebase(-16)?;
+        let words = prog.words().to_vec();
+        assert_eq!(words[1..3], [0xD000_0000, 0xD000_0003]);
+        assert!(
+            prog.rebase(253)
+                .unwrap_err()
+                .to_string()
+                .contains("moves label 'done' to 256")
+        );
+        assert!(
+            prog.rebase(-1)
+                .unwrap_err()
+                .to_string()
+                .contains("out of range")
+        );
+        assert_eq!(prog.words(), words);
+        assert_eq!(prog.labels()["start"], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mul_alu_mixup() {
+        expect_failing_program(
+            "MOV MUL, A\n",
+            "Syntax error: MUL can only be moved to P, not A. Did you mean MOV MUL,P (or MOV ALU,A)?",
+        );
+        expect_failing_program(
+            "MOV ALU, P\n",
+            "Syntax error: ALU can only be moved to A, not P. Did you mean MOV ALU,A (or MOV MUL,P)?",
+        );
+        // other destinations still get the generic error
+        expect_failing_program("MOV MUL, X\n", "Expected P but got X");
+    }
+
+    #[test]
+    fn test_relaxed_missing_comma() -> color_eyre::Result<()> {
+        let legacy = "MOV MC0 X  MOV MUL P\nJMP Z $10\nDMA D0 M1 4\nMVI #1 MC0\nMVI #2 RX Z\n";
+        let expected = "MOV MC0,X  MOV MUL,P\nJMP Z,$10\nDMA D0,M1,4\nMVI #1,MC0\nMVI #2,RX,Z\n";
+
+        let mut prog = Program::default();
+        assert!(document(&mut lex(legacy), &mut prog, false).is_err());
+
+        let mut relaxed = Program::default();
+        document(&mut lex(legacy), &mut relaxed, true)?;
+        assert_eq!(relaxed.words(), assemble_program(expected)?.words());
+
+        // a comma is still needed before the end of the line
+        let mut prog = Program::default();
+        assert!(document(&mut lex("MOV MC0\n"), &mut prog, true).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mov_accumulator_halves() -> color_eyre::Result<()> {
+        // D1-bus control 11, destination in bits 11-8, ALL (1001) or ALH (1010) in bits 3-0
+        let prog = assemble_program("MOV ALL,MC2\nMOV ALH,CT3\nSUB  MOV ALH,RX\n")?;
+        assert_eq!(prog.words(), [0x0000_3209, 0x0000_3F0A, 0x1400_340A]);
+
+        // the X and Y buses can't read the accumulator
+        expect_failing_program(
+            "MOV ALL,X\n",
+            "Syntax error: Illegal destination for MOV ALL, got: X; it can only be moved over the \
+            D1 bus",
+        );
+        expect_failing_program("MOV ALH,Y\n", "Illegal destination for MOV ALH, got: Y");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mov_d1_bus() -> color_eyre::Result<()> {
+        // D1-bus control 11, destination in bits 11-8, source in bits 3-0
+        let prog =
+            assemble_program("MOV MC0,RA0\nMOV M2,CT1\nAD2  MOV MC2,X  MOV MUL,P  MOV MC3,LOP\n")?;
+        assert_eq!(prog.words(), [0x0000_3604, 0x0000_3D02, 0x1B60_3A07]);
+
+        expect_failing_program(
+            "MOV M0,A\n",
+            "Illegal destination for MOV M0, got: A; expected X, P, Y, MC0-MC3",
+        );
+
+        // there's a single D1-bus field, so only one D1-bus move fits in a bundle
+        expect_failing_program(
+            "MOV MC0,RA0  MOV MC1,WA0\n",
+            "Illegal program: Bundle contains more than one D1-Bus instruction",
+        );
+        expect_failing_program(
+            "MOV #1,RX  MOV ALL,MC0\n",
+            "more than one D1-Bus instruction",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dma_encoding() -> color_eyre::Result<()> {
+        // bits 31-28 are 1100, bit 14 holds the D0 address, bit 13 takes the count from data RAM,
+        // bit 12 is set for DSP -> D0, bits 10-8 select the RAM and bits 7-0 are the count
+        let prog = assemble_program(
+            "DMA D0,M0,#4\nDMA M1,D0,#16\nDMAH D0,M2,MC1\nDMA D0,PRG,#8\nDMAH M3,D0,M0\n",
+        )?;
+        assert_eq!(
+            prog.words(),
+            [
+                0xC000_0004,
+                0xC000_1110,
+                0xC000_6205,
+                0xC000_0408,
+                0xC000_7300
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_mnemonic() {
+        expect_failing_program("ADDD\n", "did you mean `ADD`?");
+        expect_failing_program("  MVII 1,MC0\n", "did you mean `MVI`?");
+        expect_failing_program("NOP  JPM $10\n", "did you mean `JMP`?");
+
+        // defines are still parsed as usual, and unrelated words get no suggestion
+        validate_program("ADDR = 1\n").unwrap();
+        assert_eq!(closest_mnemonic("FOOBAR"), None);
+        assert_eq!(closest_mnemonic("X1"), None);
+
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "ADD"), 3);
+    }
+
+    #[test]
+    fn test_recover_multiple_errors() {
+        let mut prog = Program::default();
+        let errors =
+            document_recovering(&mut lex("MOV FOO, X\nCLR A\nMOV MC0 X\n"), &mut prog, false);
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|error| (error.line, error.column.clone()))
+            .collect();
+        assert_eq!(found, [(0, Some(4..7)), (2, Some(8..9))]);
+        assert!(errors[1].error.to_string().contains("Expected Comma"));
+
+        // the good line in between still assembled
+        assert_eq!(prog.words().len(), 1);
+    }
+
+    #[test]
+    fn test_same_destination() -> color_eyre::Result<()> {
+        expect_failing_program(
+            "MOV M0, X  MOV M1, X\n",
+            "Illegal program: Bundle writes to X more than once",
+        );
+        expect_failing_program("MOV M0, P  MOV MUL, P\n", "writes to P more than once");
+        expect_failing_program("MOV M0, Y  MOV M1, Y\n", "writes to Y more than once");
+        expect_failing_program("CLR A  MOV ALU, A\n", "writes to A more than once");
+
+        // one of each is fine
+        validate_program("MOV M0, X  MOV MUL, P  MOV M1, Y  CLR A\n")
+    }
+
+    #[test]
+    fn test_instructions_registered() {
+        // each kind of instruction has to be counted for the bundle to be validated, so pairing
+        // it with something it can't share a bundle with must fail
+        let cases = [
+            ("AND  OR\n", "more than one ALU instruction"),
+            ("AND  CLR A  SR\n", "more than one ALU instruction"),
+            (
+                "MOV M0,X  MOV MUL,P  MOV M1,X\n",
+                "more than 2 X-Bus instructions",
+            ),
+            (
+                "MOV M0,Y  CLR A  MOV ALU,A\n",
+                "more than 2 Y-Bus instructions",
+            ),
+            ("MVI #1,MC0  AND\n", "MVI cannot be combined"),
+            ("DMA D0,M0,#4  MOV #1,MC0\n", "DMA cannot be combined"),
+            ("CLR A  JMP start\nstart:\n", "cannot be combined"),
+        ];
+        for (source, msg) in cases {
+            expect_failing_program(source, msg);
+        }
+    }
+
+    #[test]
+    fn test_space() -> color_eyre::Result<()> {
+        let prog = assemble_program("before: CLR A\n.space 3\nafter: END\n")?;
+        assert_eq!(prog.labels()["before"], 0);
+        assert_eq!(prog.labels()["after"], 4);
+        assert_eq!(prog.words(), &[0x0002_0000, 0, 0, 0, 0xF000_0000]);
+
+        // .skip is the same, and reserved data words are filled per the pad policy too
+        let mut prog = Program::default();
+        prog.set_pad_policy(PadPolicy::Value(0xFFFF_FFFF));
+        document(
+            &mut lex("END\n.data\nscratch: .skip 2\ntable: DW 1\n"),
+            &mut prog,
+            false,
+        )?;
+        assert_eq!(prog.labels()["table"], 3);
+        assert_eq!(prog.words(), &[0xF000_0000, 0xFFFF_FFFF, 0xFFFF_FFFF, 1]);
+
+        expect_failing_program(".space 257\n", "larger than the 256 words");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_with_instruction() -> color_eyre::Result<()> {
+        // the label takes the address of the bundle on its line, which is still assembled
+        let prog = assemble_program("target: CLR A\n")?;
+        assert_eq!(prog.labels()["target"], 0);
+        assert_eq!(prog.words(), &[0x0002_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mov_missing_source() {
+        expect_failing_program(
+            "MOV , X\n",
+            "Syntax error: Expected a source operand, got ','",
+        );
+    }
+
+    #[test]
+    fn test_jmp_alone() -> color_eyre::Result<()> {
+        // JMP uses the whole instruction word, so it can't be bundled with a MOV
+        expect_failing_program(
+            "JMP target  MOV M0, X\ntarget:\n",
+            "Illegal program: JMP/BTM/LPS/END/ENDI cannot be combined with other instructions",
+        );
+        expect_failing_program(
+            "MOV M0, X  JMP NZ, target\ntarget:\n",
+            "cannot be combined with other instructions",
+        );
+
+        // on its own line it's fine
+        let prog = assemble_program("MOV M0, X\nJMP target\ntarget:\n")?;
+        assert_eq!(prog.words(), &[0x0200_0000, 0xD000_0002]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_radix_digits() {
+        expect_failing_program(
+            "MVI %102,RX\n",
+            "Syntax error: '2' is not a valid binary digit in '%102'",
+        );
+        expect_failing_program(
+            "MVI $1G,RX\n",
+            "Syntax error: 'G' is not a valid hexadecimal digit in '$1G'",
+        );
+        expect_failing_program(
+            "MVI #1a,RX\n",
+            "Syntax error: 'a' is not a valid decimal digit in '#1a'",
+        );
+        expect_failing_program(
+            "MVI $100000000,RX\n",
+            "Syntax error: '$100000000' doesn't fit in 32 bits",
+        );
+    }
+
+    #[test]
+    fn test_pc_and_bundle_progress() -> color_eyre::Result<()> {
+        let mut prog = assemble_program("CLR A\nMOV MUL,P\n")?;
+        assert_eq!(prog.pc(), 2);
+        assert_eq!(prog.current_bundle_instructions(), 0);
+
+        // part way through a bundle, nothing is committed yet
+        prog.begin();
+        prog.emit_bit(18);
+        prog.emit_bits(vec![24]);
+        assert_eq!(prog.pc(), 2);
+        assert_eq!(prog.current_bundle_instructions(), 2);
+
+        prog.flush()?;
+        assert_eq!(prog.pc(), 3);
+        assert_eq!(prog.current_bundle_instructions(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dma_hold_bit() -> color_eyre::Result<()> {
+        for (dma, dmah) in [
+            ("DMA D0,M1,#16\n", "DMAH D0,M1,#16\n"),
+            ("DMA M2,D0,MC0\n", "DMAH M2,D0,MC0\n"),
+        ] {
+            let dma = assemble_program(dma)?.words()[0];
+            let dmah = assemble_program(dmah)?.words()[0];
+            assert_eq!(dma ^ dmah, 1 << 14);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dma_one_per_bundle() {
+        // DMA and DMAH share the one DMA controller
+        for bundle in [
+            "DMA D0,M0,#1  DMA D0,M1,#1\n",
+            "DMAH D0,M0,#1  DMAH D0,M1,#1\n",
+            "DMA D0,M0,#1  DMAH M1,D0,#1\n",
+        ] {
+            expect_failing_program(
+                bundle,
+                "Illegal program: DMA cannot be combined with other instructions",
+            );
+        }
+
+        expect_failing_program(
+            "DMA M0,M1,#1\n",
+            "Illegal DMA transfer from M0 to M1; one side must be D0 and the other M0-M3",
+        );
+    }
+
+    #[test]
+    fn test_end_not_combined() {
+        expect_failing_program(
+            "ADD END\n",
+            "Illegal program: END cannot be combined with other instructions in a bundle",
+        );
+        expect_failing_program(
+            "CLR A  ENDI\n",
+            "Illegal program: ENDI cannot be combined with other instructions in a bundle",
+        );
+        expect_failing_program(
+            "MOV M0,X BTM\n",
+            "Illegal program: BTM cannot be combined with other instructions in a bundle",
+        );
+        expect_failing_program(
+            "CLR A  JMP $10\n",
+            "Illegal program: JMP cannot be combined with other instructions in a bundle",
+        );
+    }
+
+    #[test]
+    fn test_one_bundle_per_line() -> color_eyre::Result<()> {
+        // each line is flushed as its own bundle, however many instructions it has
+        let prog = assemble_program("AD2  MOV MUL,P  CLR A\nMOV M0,X\n")?;
+        assert_eq!(prog.words().len(), 2);
+        assert_eq!(prog.words()[1], 0x0200_0000);
+        assert!(!prog.is_emitting());
+
+        // blank lines and comments don't produce empty bundles
+        let prog = assemble_program("\nCLR A\n\n; comment\nCLR A\n\n")?;
+        assert_eq!(prog.words(), &[0x0002_0000, 0x0002_0000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_line() {
+        // the syntax error is on line 4 (index 3), not the newline after it
+        let mut tokens = lex("CLR A\nMOV MUL,P\nAD2\nMOV ???,X\nCLR A\n");
+        let mut prog = Program::default();
+        assert!(document(&mut tokens, &mut prog, false).is_err());
+        assert_eq!(prog.line, 3);
+
+        // same again for an error caught when the bundle is flushed, at the end of the line
+        let mut tokens = lex("CLR A\nMOV MUL,P\nAD2\nAND OR\nCLR A\n");
+        let mut prog = Program::default();
+        assert!(document(&mut tokens, &mut prog, false).is_err());
+        assert_eq!(prog.line, 3);
+    }
+
+    #[test]
+    fn test_mov_y_uses_y_bus() -> color_eyre::Result<()> {
+        let word = assemble_bundle("MOV M2, Y")?;
+
+        // Y-bus load bit, and M2 (0b010) in the Y-bus source field at bit 14
+        assert!(word.is_set(19));
+        assert_eq!(word.get_bits(3, 14), 0b010);
+
+        // none of the X-bus/P bits
+        assert!(!word.is_set(25));
+        assert_eq!(word.get_bits(2, 23), 0);
+        assert_eq!(word.get_bits(3, 20), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_maxbundle() -> color_eyre::Result<()> {
+        let bundle = "AD2  MOV MC2,X  MOV MUL,P  MOV MC3,Y  MOV ALU,A\n";
+        validate_program(bundle)?;
+
+        let source = format!(".maxbundle 4\n{bundle}");
+        let mut prog = Program::default();
+        let result = document(&mut lex(&source), &mut prog, false);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("More than 4 instructions issued in a single bundle")
+        );
+
+        // the command line wins over the source
+        let mut prog = Program::default();
+        prog.set_max_bundle_override(Some(6))?;
+        document(&mut lex(&source), &mut prog, false)?;
+        assert!(prog.warnings()[0].message.contains(".maxbundle 4 ignored"));
+
+        expect_failing_program(
+            ".maxbundle 7\n",
+            "Bundle limit 7 is out of range (must be 1-6)",
+        );
+        expect_failing_program(".maxbundle 0\n", "Bundle limit 0 is out of range");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mov_simm() -> color_eyre::Result<()> {
+        assert_eq!(assemble_bundle("MOV #5, MC0")?, 0x0000_1005);
+        assert_eq!(assemble_bundle("MOV #-3, RX")?, 0x0000_14FD);
+        assert_eq!(assemble_bundle("MOV #127, CT3")?, 0x0000_1F7F);
+        assert_eq!(assemble_bundle("MOV #-128, LOP")?, 0x0000_1A80);
+        assert_eq!(assemble_bundle("MOV 0, MC1")?, 0x0000_1100);
+
+        // it shares a bundle with the other operation instructions
+        assert_eq!(assemble_bundle("AD2  MOV MUL,P  MOV #1,PL")?, 0x1900_1501);
+
+        expect_failing_program("MOV #128, MC0\n", "'128' will not fit in signed 8-bit");
+        expect_failing_program("MOV #-129, MC0\n", "'-129' will not fit in signed 8-bit");
+        expect_failing_program("MOV #5, A\n", "Illegal destination for MOV SImm, got: A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digit_separators() -> color_eyre::Result<()> {
+        let prog = assemble_program("MVI $1_BEEF,RX\nMVI #1_000,LOP\nMVI %1010_1010,MC0\n")?;
+        assert_eq!(prog.words()[0] & 0x1FF_FFFF, 0x1_BEEF);
+        assert_eq!(prog.words()[1] & 0x1FF_FFFF, 1000);
+        assert_eq!(prog.words()[2] & 0x1FF_FFFF, 0b1010_1010);
+
+        for bad in ["$_1", "$1__2", "#1_", "%1_"] {
+            let source = format!("MVI {bad},RX\n");
+            let result = document(&mut lex(&source), &mut Program::default(), false);
+            assert!(result.unwrap_err().to_string().contains(&format!(
+                "Misplaced '_' in '{bad}'; underscores can only go between digits"
+            )));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hex_digits() -> color_eyre::Result<()> {
+        assert_eq!(parse_num("$FF")?, 0xFF);
+        assert_eq!(parse_num("$1a")?, 0x1A);
+        assert_eq!(parse_num("%1010")?, 0b1010);
+
+        // a full 32-bit word is kept as is in data, and anything wider is an error rather than
+        // being silently truncated
+        assert_eq!(parse_num("$deadbeef")?, 0xDEAD_BEEF);
+        let prog = assemble_program(".data\nDW $deadbeef\n")?;
+        assert_eq!(prog.words(), &[0xDEAD_BEEF]);
+        assert!(parse_num("$1deadbeef").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_for_pass() -> color_eyre::Result<()> {
+        let source = "COUNT EQU 3\nJMP done\nMVI COUNT,LOP\ndone:\nEND\n";
+        let first = assemble_program(source)?;
+
+        let mut second = first.clone_for_pass();
+        assert!(second.words().is_empty());
+        assert_eq!(second.pc(), 0);
+        assert_eq!(second.labels()["done"], 2);
+        assert!(second.is_define("COUNT"));
+
+        // the second pass assembles the same program, with the label known before it's declared
+        document(&mut lex(source), &mut second, false)?;
+        assert_eq!(second.words(), first.words());
+
+        // but a define can still only be declared once per pass
+        let mut third = first.clone_for_pass();
+        let result = document(&mut lex("COUNT EQU 3\nCOUNT EQU 3\n"), &mut third, false);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("already been declared")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jmp_conditions() -> color_eyre::Result<()> {
+        let always = assemble_bundle("JMP $10")?;
+        let nz = assemble_bundle("JMP NZ, $10")?;
+        assert_eq!(always, 0xD000_0010);
+        assert_eq!(nz, 0xD208_0010);
+
+        // they only differ in the condition field, bits 25-19
+        assert_eq!(always ^ nz, 0b100_0001 << 19);
+
+        // every condition gets its own encoding
+        let mut words: Vec<u32> = CONDITIONS
+            .iter()
+            .map(|(cond, _)| assemble_bundle(&format!("JMP {}, $10", cond.as_ref())))
+            .collect::<color_eyre::Result<_>>()?;
+        words.push(always);
+        words.sort();
+        words.dedup();
+        assert_eq!(words.len(), CONDITIONS.len() + 1);
+
+        expect_failing_program("JMP NZ\n", "Syntax error");
+        expect_failing_program("JMP $100\n", "out of range for a JMP target");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jmp_labels() -> color_eyre::Result<()> {
+        let prog = assemble_program(
+            "start:\n\
+             JMP NZ, skip\n\
+             CLR A\n\
+             MOV MUL, P\n\
+             skip:\n\
+             JMP start\n",
+        )?;
+
+        // the forward jump is patched once the label is declared, the backward one immediately
+        let skip = prog.labels()["skip"];
+        assert_eq!(skip, 3);
+        assert_eq!(prog.words()[0], 0xD208_0000 | skip);
+        assert_eq!(prog.words()[3], 0xD000_0000 | prog.labels()["start"]);
+
+        expect_failing_program(
+            "JMP nowhere\n",
+            "'nowhere' is not a declared define or label",
+        );
+
+        Ok(())
+    }
+
+    /// Tokens that are lexed but not yet assembled by any parser path. Keep this up to date, so
+    /// the gap between what can be lexed and what can be assembled stays visible.
+    const UNUSED_TOKENS: &[&str] = &[
+        // lexed, but the parser doesn't handle ENDS yet
+        "Ends", // skipped by the lexer, so never seen by the parser
+        "Comment",
+        // bit ranges are rejected at the opening bracket, so this is never reached
+        "RBracket",
+    ];
+
+    #[test]
+    fn test_unused_tokens() {
+        use strum::VariantNames;
+
+        // only look at the parser itself, not these tests
+        let source = include_str!("parser.rs");
+        let source = &source[..source.find("#[cfg(test)]").unwrap()];
+
+        // tokens are spelt either through the `T` alias or in full
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let is_used = |name: &str| {
+            ["T", "ScuDspToken"].iter().any(|prefix| {
+                let pattern = format!("{prefix}::{name}");
+                source.match_indices(&pattern).any(|(i, _)| {
+                    !source[..i].ends_with(is_ident)
+                        && !source[i + pattern.len()..].starts_with(is_ident)
+                })
+            })
+        };
+        let unused: Vec<&str> = ScuDspToken::VARIANTS
+            .iter()
+            .copied()
+            .filter(|name| !is_used(name))
+            .collect();
+
+        assert_eq!(
+            unused, UNUSED_TOKENS,
+            "the tokens the parser doesn't use have changed, update UNUSED_TOKENS"
+        );
+    }
 }
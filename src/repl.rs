@@ -0,0 +1,106 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::{BufRead, Write};
+
+use crate::{emitter::Program, parser::assemble_line};
+
+const PROMPT: &str = "> ";
+
+/// Runs an interactive read-eval-print loop. Each line is assembled against the same program, so
+/// labels and defines carry over between lines. Returns when the input reaches EOF.
+pub fn repl<R: BufRead, W: Write>(
+    mut input: R,
+    output: &mut W,
+    relaxed: bool,
+) -> color_eyre::Result<()> {
+    let mut prog = Program::default();
+
+    loop {
+        write!(output, "{PROMPT}")?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            // EOF (Ctrl-D), finish the prompt line so the shell doesn't end up after it
+            writeln!(output)?;
+            break;
+        }
+
+        let line = line.trim();
+        match line {
+            "" => {}
+            ".labels" => {
+                for (label, addr) in prog.sorted_labels() {
+                    writeln!(output, "{label}: {addr:#04x}")?;
+                }
+            }
+            ".reset" => {
+                prog = Program::default();
+                writeln!(output, "Program reset")?;
+            }
+            ".dump" => {
+                for (i, word) in prog.words().iter().enumerate() {
+                    writeln!(output, "[{i}] {word:#010x}")?;
+                }
+            }
+            _ if line.starts_with('.') => {
+                writeln!(
+                    output,
+                    "Unknown command '{line}' (expected .labels, .reset or .dump)"
+                )?;
+            }
+            _ => match assemble_line(line, &mut prog, relaxed) {
+                Ok(Some(word)) => writeln!(output, "{word:#010x}")?,
+                Ok(None) => {}
+                Err(error) => writeln!(output, "{error}")?,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_script(script: &str) -> color_eyre::Result<String> {
+        let mut output = Vec::new();
+        repl(script.as_bytes(), &mut output, false)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_repl_script() -> color_eyre::Result<()> {
+        let output = run_script("CLR A\nstart:\nMOV MUL, P\n.labels\n.dump\n.reset\n.dump\n")?;
+
+        assert_eq!(
+            output,
+            "> 0x00020000\n\
+             > > 0x01000000\n\
+             > start: 0x01\n\
+             > [0] 0x00020000\n\
+             [1] 0x01000000\n\
+             > Program reset\n\
+             > > \n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repl_error_recovers() -> color_eyre::Result<()> {
+        let output = run_script("AD2 OR\nCLR A\n")?;
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines[0].contains("Illegal program"));
+        assert_eq!(lines[1], "> 0x00020000");
+
+        Ok(())
+    }
+}
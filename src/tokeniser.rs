@@ -5,8 +5,8 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
 // was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 use logos::{Lexer, Logos, Skip};
-use std::iter::Peekable;
-use strum::AsRefStr;
+use std::ops::Range;
+use strum::{AsRefStr, VariantNames};
 
 /// Drops the last character from the string. Used to drop ':' from labels. Slow!
 fn drop_last(string: String) -> String {
@@ -17,7 +17,7 @@ fn drop_last(string: String) -> String {
 
 // TODO we also do want to lex newline (instructions are packed on each line)
 
-#[derive(Logos, Debug, PartialEq, Eq, AsRefStr, Clone)]
+#[derive(Logos, Debug, PartialEq, Eq, AsRefStr, VariantNames, Clone)]
 #[logos(skip r"[ \t]+")] // Ignore this regex pattern between tokens
 pub enum ScuDspToken {
     // Generic instrs
@@ -72,6 +72,9 @@ pub enum ScuDspToken {
     #[regex("(?i)lop")]
     Lop,
 
+    #[regex("(?i)pc")]
+    Pc,
+
     #[regex("(?i)top")]
     Top,
 
@@ -188,6 +191,9 @@ pub enum ScuDspToken {
     #[regex("(?i)d0")]
     D0,
 
+    #[regex("(?i)prg")]
+    Prg,
+
     // Jump
     #[regex("(?i)jmp")]
     Jmp,
@@ -225,15 +231,48 @@ pub enum ScuDspToken {
     #[regex("(?i)endif")]
     Endif,
 
+    // Sections and data
+    #[regex("(?i)\\.code")]
+    Code,
+
+    #[regex("(?i)\\.data")]
+    Data,
+
+    #[regex("(?i)dw")]
+    Dw,
+
+    #[regex("(?i)\\.(space|skip)")]
+    Space,
+
+    // Pragmas
+    #[regex("(?i)\\.maxbundle")]
+    Maxbundle,
+
     #[token("=")]
     Equals,
 
+    // Comparisons (for IF)
+    #[token("==")]
+    EqualsEquals,
+
+    #[token("!=")]
+    NotEquals,
+
+    #[token("<")]
+    Less,
+
+    #[token(">")]
+    Greater,
+
     // Generic tokens
     #[regex("[a-zA-Z][a-zA-Z0-9_]*", |lex| lex.slice().to_owned())]
     Ident(String),
 
-    // $xx = hex, #xx = decimal, %xx = binary
-    #[regex("[#|\\$|%]?[0-9]+", |lex| lex.slice().to_owned())]
+    // $xx = hex, #xx = decimal, %xx = binary, any of which may be negative and use `_` to separate
+    // digits. Trailing letters are swallowed too, so that a bad digit is reported against the
+    // whole literal rather than as a stray identifier.
+    #[regex("[#\\$%]?-?[0-9][0-9a-zA-Z_]*", |lex| lex.slice().to_owned())]
+    #[regex("\\$-?[a-zA-Z_][0-9a-zA-Z_]*", |lex| lex.slice().to_owned())]
     Num(String),
 
     // label must start with alpha but can otherwise use whatever
@@ -249,7 +288,14 @@ pub enum ScuDspToken {
     #[token("\\")]
     Backslash,
 
-    #[regex("[\r]?\n+")]
+    #[token("[")]
+    LBracket,
+
+    #[token("]")]
+    RBracket,
+
+    // one token per line, so that line numbers can be tracked
+    #[regex("\r?\n")]
     Newline,
 }
 
@@ -267,9 +313,69 @@ impl ScuDspToken {
     }
 }
 
-/// Lexes an asm document
-pub fn lex<'l>(document: &'l str) -> Peekable<Lexer<'l, ScuDspToken>> {
-    ScuDspToken::lexer(document).peekable()
+/// A peekable stream of tokens. Works like `Peekable<Lexer>`, but keeps hold of the lexer so the
+/// parser can find out where in the source a token came from.
+pub struct Tokens<'l> {
+    lexer: Lexer<'l, ScuDspToken>,
+    peeked: Option<Option<Result<ScuDspToken, ()>>>,
+
+    /// Byte span of the token an error would point at, see [`Tokens::mark`]
+    marked: Option<Range<usize>>,
+}
+
+impl<'l> Tokens<'l> {
+    /// Returns, but does not remove, the next token
+    pub fn peek(&mut self) -> Option<&Result<ScuDspToken, ()>> {
+        self.peeked
+            .get_or_insert_with(|| self.lexer.next())
+            .as_ref()
+    }
+
+    /// Byte span of the most recently lexed token. Like [`Lexer::span`], this is the peeked token
+    /// if there is one.
+    pub fn span(&self) -> Range<usize> {
+        self.lexer.span()
+    }
+
+    /// Marks the next token as the one any error from here on is about, until something else is
+    /// marked
+    pub fn mark(&mut self) {
+        self.peek();
+        self.marked = Some(self.span());
+    }
+
+    /// Columns (in characters, starting at 0) the marked token covers on its line, if any
+    pub fn marked_columns(&self) -> Option<Range<u32>> {
+        let span = self.marked.clone()?;
+        let source = self.lexer.source();
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+
+        let start = source[line_start..span.start].chars().count() as u32;
+        // the newline token is a character wide too, so the caret lands at the end of the line
+        let width = source[span].chars().count().max(1) as u32;
+        Some(start..start + width)
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Result<ScuDspToken, ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(tok) => tok,
+            None => self.lexer.next(),
+        }
+    }
+}
+
+/// Lexes an asm document. A leading UTF-8 byte order mark, as written by some editors, is skipped.
+pub fn lex<'l>(document: &'l str) -> Tokens<'l> {
+    let document = document.strip_prefix('\u{FEFF}').unwrap_or(document);
+    Tokens {
+        lexer: ScuDspToken::lexer(document),
+        peeked: None,
+        marked: None,
+    }
 }
 
 #[cfg(test)]
@@ -353,4 +459,71 @@ mod tests {
 
         assert_eq!(lex.next(), None);
     }
+
+    #[test]
+    fn test_byte_order_mark() {
+        let with_bom: Vec<_> = lex("\u{FEFF}CLR A\nMOV MUL, P\n").collect();
+        let without_bom: Vec<_> = lex("CLR A\nMOV MUL, P\n").collect();
+        assert_eq!(with_bom, without_bom);
+    }
+
+    #[test]
+    fn test_newline_per_line() {
+        let mut lex = ScuDspToken::lexer("\n\r\n\n");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Newline)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Newline)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Newline)));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_hex_digits() {
+        let mut lex = lex("$FF $deadbeef $1A %1010 #1a");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$FF".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$deadbeef".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$1A".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("%1010".into()))));
+        // letters are only digits in hex, but are still lexed as part of the number so the parser
+        // can say which digit is wrong
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("#1a".into()))));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        let mut lex = lex("#-5, -5 $-1F");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("#-5".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Comma)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("-5".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$-1F".into()))));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let mut lex = lex("$DEAD_BEEF #1_000 %1010_1010 $_1 $1__2");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$DEAD_BEEF".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("#1_000".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("%1010_1010".into()))));
+        // lexed whole, so the parser can reject them with a useful error
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$_1".into()))));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Num("$1__2".into()))));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_end_prefixes() {
+        let mut lex = ScuDspToken::lexer("end endi endif ends endix");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::End)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Endi)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Endif)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Ends)));
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Ident("endix".into()))));
+        assert_eq!(lex.next(), None);
+
+        // ENDIF mustn't be split into ENDI followed by F
+        let mut lex = ScuDspToken::lexer("ENDIF");
+        assert_eq!(lex.next(), Some(Ok(ScuDspToken::Endif)));
+        assert_eq!(lex.next(), None);
+    }
 }
@@ -0,0 +1,41 @@
+// SoCUte: An assembler for the Sega Saturn SCU DSP.
+//
+// Copyright (c) 2025 Matt Young.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0. If a copy of the MPL
+// was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Checks the library entry point works for other Rust programs
+
+#[test]
+fn test_assemble() -> color_eyre::Result<()> {
+    let bytes = socute::assemble("start: CLR A  MOV MUL,P\nJMP start\nEND", false)?;
+    assert_eq!(
+        bytes,
+        [
+            0x00, 0x00, 0x02, 0x01, // CLR A  MOV MUL,P
+            0x00, 0x00, 0x00, 0xD0, // JMP start
+            0x00, 0x00, 0x00, 0xF0, // END
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_assemble_error() {
+    let error = socute::assemble("AND OR\n", false).unwrap_err();
+    assert!(error.to_string().contains("more than one ALU instruction"));
+}
+
+#[test]
+fn test_assemble_reader() -> color_eyre::Result<()> {
+    // stands in for stdin, as read by `socute asm -`
+    let stdin = std::io::Cursor::new("CLR A\nEND\n");
+    assert_eq!(
+        socute::assemble_reader(stdin, false)?,
+        socute::assemble("CLR A\nEND\n", false)?
+    );
+
+    Ok(())
+}